@@ -0,0 +1,19 @@
+use std::error::Error;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut config = prost_build::Config::new();
+    config.protoc_executable(protoc_bin_vendored::protoc_bin_path()?);
+
+    let well_known_types = protoc_bin_vendored::include_path()?;
+
+    tonic_prost_build::configure()
+        .build_client(true)
+        .build_server(true)
+        .compile_with_config(
+            config,
+            &["proto/atlas.proto".into()],
+            &["proto".into(), well_known_types],
+        )?;
+
+    Ok(())
+}
@@ -0,0 +1,24 @@
+use crate::mirror::constant_time_eq;
+
+use actix_web::http::header::{AUTHORIZATION, HeaderMap};
+
+/// Settings of the administrative endpoints under `/admin`, which are only served when a token
+/// is configured
+pub struct AdminConfig {
+    token: String,
+}
+
+impl AdminConfig {
+    pub fn new(token: String) -> Self {
+        Self { token }
+    }
+
+    /// Whether the request carries the admin token as a bearer token
+    pub fn is_authorized(&self, headers: &HeaderMap) -> bool {
+        headers
+            .get(AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|authorization| authorization.strip_prefix("Bearer "))
+            .is_some_and(|token| constant_time_eq(token.trim().as_bytes(), self.token.as_bytes()))
+    }
+}
@@ -1,4 +1,4 @@
-use crate::models::{HealthCheckModel, LookupResponseModel, LookupResult};
+use crate::models::{FlatLookupResult, HealthCheckModel, LookupResponseModel, LookupResult};
 use crate::services;
 use serde_json::json;
 use std::borrow::Cow;
@@ -14,8 +14,17 @@ use utoipa::{OpenApi, PartialSchema};
         title = "Atlas GeoIP",
         description = "Atlas GeoIP Service API Documentation [Github Repo](https://github.com/alisinabh/atlas-rs)"
     ),
-    paths(services::healthcheck::handle, services::lookup::handle),
-    components(schemas(LookupResponseModel, LookupResult, HealthCheckModel)),
+    paths(
+        services::healthcheck::handle,
+        services::lookup::handle,
+        services::whoami::handle
+    ),
+    components(schemas(
+        LookupResponseModel,
+        LookupResult,
+        FlatLookupResult,
+        HealthCheckModel
+    )),
     tags(
         (name = "GeoIP", description = "IP GeoLocation Endpoints"),
         (name = "Health", description = "Healthcheck Endpoints")
@@ -87,7 +96,9 @@ impl LookupResult<'_> {
             .item(LookupResult::connection_type_schema())
             .item(LookupResult::country_schema())
             .item(LookupResult::density_income_schema())
-            .item(LookupResult::isp_schema());
+            .item(LookupResult::isp_schema())
+            .item(LookupResult::raw_schema())
+            .item(utoipa::openapi::Ref::from_schema_name("FlatLookupResult"));
 
         utoipa::openapi::Schema::OneOf(builder.into())
     }
@@ -624,6 +635,17 @@ impl LookupResult<'_> {
             )
     }
 
+    fn raw_schema() -> ObjectBuilder {
+        ObjectBuilder::new()
+            .schema_type(Type::Object)
+            .title(Some("RawLookupResult"))
+            .description(Some(
+                "The record as stored in the database. Its fields depend on the database.",
+            ))
+            .additional_properties(Some(AdditionalProperties::FreeForm(true)))
+            .examples([json!({"data_center": true, "country": {"iso_code": "US"}})])
+    }
+
     fn isp_schema() -> ObjectBuilder {
         ObjectBuilder::new()
             .schema_type(Type::Object)
@@ -0,0 +1,263 @@
+use crate::rate_limit::{LimitExceeded, TokenBucket};
+use crate::services::{too_many_requests, unauthorized};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{HttpMessage, web};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const API_KEY_HEADER: &str = "X-API-Key";
+pub const API_KEY_QUERY_PARAM: &str = "api_key";
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// The API key which was used to authenticate the request. Available in request extensions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthenticatedKey(pub String);
+
+#[derive(Debug)]
+struct ApiKey {
+    daily_quota: Option<u64>,
+    usage: Mutex<ApiKeyUsage>,
+}
+
+#[derive(Debug)]
+struct ApiKeyUsage {
+    rate_limit: Option<TokenBucket>,
+    day: u64,
+    used_today: u64,
+}
+
+impl ApiKey {
+    /// Records a request made with this key if it is within its limits.
+    fn record_request(&self) -> Result<(), Rejection> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let today = now / SECONDS_PER_DAY;
+
+        let mut usage = self.usage.lock().unwrap();
+
+        if usage.day != today {
+            usage.day = today;
+            usage.used_today = 0;
+        }
+
+        if let Some(daily_quota) = self.daily_quota
+            && usage.used_today >= daily_quota
+        {
+            let until_tomorrow = (today + 1) * SECONDS_PER_DAY - now;
+            return Err(Rejection::QuotaExceeded(Duration::from_secs(
+                until_tomorrow,
+            )));
+        }
+
+        if let Some(rate_limit) = usage.rate_limit.as_mut() {
+            rate_limit
+                .try_take(1)
+                .map_err(|limit_exceeded| match limit_exceeded {
+                    LimitExceeded::RetryAfter(retry_after) => Rejection::RateLimited(retry_after),
+                    // Keys allow at least one request per minute, so this is only a safeguard
+                    LimitExceeded::TooManyIps { .. } => {
+                        Rejection::RateLimited(Duration::from_secs(60))
+                    }
+                })?;
+        }
+
+        usage.used_today += 1;
+
+        Ok(())
+    }
+}
+
+/// Why a request was not allowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// The API key is missing or unknown
+    Unauthorized,
+    /// Retry after the duration
+    RateLimited(Duration),
+    /// Retry after the duration
+    QuotaExceeded(Duration),
+}
+
+/// Set of API keys which are allowed to call the lookup endpoints along with their usage limits.
+#[derive(Debug, Default)]
+pub struct ApiKeyStore {
+    keys: HashMap<String, ApiKey>,
+}
+
+impl ApiKeyStore {
+    /// Loads API keys from a comma separated list of entries and/or a file with one entry per line.
+    /// Returns `None` when neither is given, meaning that authentication is disabled.
+    ///
+    /// Each entry has the format `key[:requests_per_minute[:daily_quota]]`. Limits which are
+    /// omitted or empty are unlimited, set limits must be positive. Empty lines and lines starting with `#` are ignored.
+    pub async fn load(
+        keys: Option<&str>,
+        keys_file: Option<&str>,
+    ) -> Result<Option<Self>, Box<dyn Error>> {
+        if keys.is_none() && keys_file.is_none() {
+            return Ok(None);
+        }
+
+        let mut store = Self::default();
+
+        if let Some(keys) = keys {
+            for entry in keys.split(',') {
+                store.add_entry(entry)?;
+            }
+        }
+
+        if let Some(keys_file) = keys_file {
+            let content = tokio::fs::read_to_string(keys_file).await?;
+
+            for line in content.lines() {
+                store.add_entry(line)?;
+            }
+        }
+
+        Ok(Some(store))
+    }
+
+    fn add_entry(&mut self, entry: &str) -> Result<(), Box<dyn Error>> {
+        let entry = entry.trim();
+
+        if entry.is_empty() || entry.starts_with('#') {
+            return Ok(());
+        }
+
+        let mut parts = entry.split(':').map(str::trim);
+        let key = parts.next().unwrap_or_default().to_string();
+
+        if key.is_empty() {
+            return Err(format!("Missing API key in entry {entry:?}").into());
+        }
+
+        let rate_limit = match parts.next().filter(|p| !p.is_empty()) {
+            Some(limit) => Some(TokenBucket::new(
+                limit
+                    .parse()
+                    .ok()
+                    .filter(|limit| *limit > 0)
+                    .ok_or("Invalid API key rate limit")?,
+                Duration::from_secs(60),
+            )),
+            None => None,
+        };
+
+        let daily_quota = match parts.next().filter(|p| !p.is_empty()) {
+            Some(quota) => Some(
+                quota
+                    .parse()
+                    .ok()
+                    .filter(|quota| *quota > 0)
+                    .ok_or("Invalid API key daily quota")?,
+            ),
+            None => None,
+        };
+
+        self.keys.insert(
+            key,
+            ApiKey {
+                daily_quota,
+                usage: Mutex::new(ApiKeyUsage {
+                    rate_limit,
+                    day: 0,
+                    used_today: 0,
+                }),
+            },
+        );
+
+        Ok(())
+    }
+}
+
+impl ApiKeyStore {
+    /// Checks `key` and records a request made with it if it is within its limits.
+    pub fn authenticate(&self, key: Option<&str>) -> Result<AuthenticatedKey, Rejection> {
+        let (key, api_key) = key
+            .and_then(|key| self.keys.get_key_value(key))
+            .ok_or(Rejection::Unauthorized)?;
+
+        api_key.record_request()?;
+
+        Ok(AuthenticatedKey(key.clone()))
+    }
+
+    /// Records another request made with an authenticated key if it is within its limits, e.g. a
+    /// message of a stream.
+    pub fn record_request(&self, key: &AuthenticatedKey) -> Result<(), Rejection> {
+        self.keys
+            .get(&key.0)
+            .ok_or(Rejection::Unauthorized)?
+            .record_request()
+    }
+}
+
+fn request_api_key(req: &ServiceRequest) -> Option<String> {
+    if let Some(key) = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|key| key.to_str().ok())
+    {
+        return Some(key.to_string());
+    }
+
+    web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .ok()
+        .and_then(|mut query| query.remove(API_KEY_QUERY_PARAM))
+}
+
+/// Middleware which requires a valid API key on GeoIP endpoints when an `ApiKeyStore` is
+/// registered as app data.
+pub async fn require_api_key(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let Some(store) = req.app_data::<web::Data<ApiKeyStore>>().cloned() else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    };
+
+    // The path as it is routed, percent-encoded characters in the raw path are decoded
+    if !req.match_info().as_str().starts_with("/geoip/") {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    }
+
+    let resp = match store.authenticate(request_api_key(&req).as_deref()) {
+        Ok(key) => {
+            req.extensions_mut().insert(key);
+            return next
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body);
+        }
+        Err(Rejection::Unauthorized) => unauthorized(
+            "Missing or invalid API key".to_string(),
+            "UNAUTHORIZED".to_string(),
+        ),
+        Err(Rejection::RateLimited(retry_after)) => too_many_requests(
+            "Rate limit exceeded for API key".to_string(),
+            "RATE_LIMITED".to_string(),
+            Some(retry_after),
+        ),
+        Err(Rejection::QuotaExceeded(retry_after)) => too_many_requests(
+            "Daily quota exceeded for API key".to_string(),
+            "QUOTA_EXCEEDED".to_string(),
+            Some(retry_after),
+        ),
+    };
+
+    Ok(req.into_response(resp).map_into_right_body())
+}
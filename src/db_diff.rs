@@ -0,0 +1,446 @@
+use crate::maxmind_db::db_file;
+
+use ipnet::{Ipv4Subnets, Ipv6Subnets};
+use maxminddb::{MaxMindDbError, Reader, WithinOptions};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::rc::Rc;
+
+/// Output format of a [`DiffReport`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffFormat {
+    /// The whole report
+    Json,
+    /// One row per changed, added or removed network
+    Csv,
+    /// One row per country with its network counts
+    CountryCsv,
+}
+
+impl DiffFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Self::Json),
+            "csv" => Some(Self::Csv),
+            "country-csv" => Some(Self::CountryCsv),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Csv | Self::CountryCsv => "text/csv",
+        }
+    }
+}
+
+/// Differences in the country and ASN of the networks of two databases
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiffReport {
+    pub old: DbSummary,
+    pub new: DbSummary,
+    /// Number of networks whose country or ASN changed
+    pub changed: u64,
+    /// Number of networks only in the new database
+    pub added: u64,
+    /// Number of networks only in the old database
+    pub removed: u64,
+    pub countries: Vec<CountryCounts>,
+    pub networks: Vec<NetworkChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DbSummary {
+    pub path: String,
+    pub database_type: String,
+    pub build_epoch: u64,
+    /// Number of networks with data
+    pub networks: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CountryCounts {
+    pub country: String,
+    /// Networks of the country in the old database
+    pub old_networks: u64,
+    /// Networks of the country in the new database
+    pub new_networks: u64,
+    /// Networks added to or changed to the country
+    pub gained: u64,
+    /// Networks removed from or changed from the country
+    pub lost: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Changed,
+    Added,
+    Removed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NetworkChange {
+    pub change: ChangeKind,
+    pub network: String,
+    pub old_country: Option<String>,
+    pub new_country: Option<String>,
+    pub old_asn: Option<u32>,
+    pub new_asn: Option<u32>,
+}
+
+/// The compared fields of a record. Country databases have no ASN and ASN databases no country.
+#[derive(Debug, Default, PartialEq, Deserialize)]
+struct NetworkData {
+    country: Option<RecordCountry>,
+    autonomous_system_number: Option<u32>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct RecordCountry {
+    iso_code: Option<String>,
+}
+
+impl NetworkData {
+    fn country(&self) -> Option<&str> {
+        self.country.as_ref()?.iso_code.as_deref()
+    }
+}
+
+/// Inclusive range of addresses with the same data. IPv4 addresses are mapped to `::/96`, where
+/// IPv6 databases keep them.
+struct Range {
+    start: u128,
+    end: u128,
+    data: Rc<NetworkData>,
+}
+
+struct Pending {
+    change: ChangeKind,
+    start: u128,
+    end: u128,
+    old: Option<Rc<NetworkData>>,
+    new: Option<Rc<NetworkData>>,
+}
+
+/// Compares the databases at `old_path` and `new_path`, which are `.mmdb` files or directories
+/// containing the one of `variant` (see [`db_file`])
+pub fn diff(
+    old_path: &Path,
+    new_path: &Path,
+    variant: Option<&str>,
+) -> Result<DiffReport, Box<dyn Error>> {
+    let old_path = db_file(old_path, variant)?;
+    let new_path = db_file(new_path, variant)?;
+    let old_reader = Reader::open_readfile(&old_path)
+        .map_err(|error| format!("Failed to open {}: {error}", old_path.display()))?;
+    let new_reader = Reader::open_readfile(&new_path)
+        .map_err(|error| format!("Failed to open {}: {error}", new_path.display()))?;
+
+    let mut report = DiffReport {
+        old: summary(&old_path, &old_reader),
+        new: summary(&new_path, &new_reader),
+        changed: 0,
+        added: 0,
+        removed: 0,
+        countries: Vec::new(),
+        networks: Vec::new(),
+    };
+    let mut countries: BTreeMap<String, CountryCounts> = BTreeMap::new();
+
+    let mut old_ranges = ranges(&old_reader)?;
+    let mut new_ranges = ranges(&new_reader)?;
+    let mut next_old = |report: &mut DiffReport, countries: &mut BTreeMap<_, _>| {
+        let range = old_ranges.next().transpose()?;
+        if let Some(range) = &range {
+            report.old.networks += 1;
+            if let Some(country) = range.data.country() {
+                country_counts(countries, country).old_networks += 1;
+            }
+        }
+        Ok::<_, MaxMindDbError>(range)
+    };
+    let mut next_new = |report: &mut DiffReport, countries: &mut BTreeMap<_, _>| {
+        let range = new_ranges.next().transpose()?;
+        if let Some(range) = &range {
+            report.new.networks += 1;
+            if let Some(country) = range.data.country() {
+                country_counts(countries, country).new_networks += 1;
+            }
+        }
+        Ok::<_, MaxMindDbError>(range)
+    };
+
+    let mut old = next_old(&mut report, &mut countries)?;
+    let mut new = next_new(&mut report, &mut countries)?;
+    let mut pending: Option<Pending> = None;
+
+    loop {
+        let change = match (&mut old, &mut new) {
+            (None, None) => break,
+            (Some(o), None) => {
+                let change = (
+                    ChangeKind::Removed,
+                    o.start,
+                    o.end,
+                    Some(o.data.clone()),
+                    None,
+                );
+                old = next_old(&mut report, &mut countries)?;
+                change
+            }
+            (None, Some(n)) => {
+                let change = (
+                    ChangeKind::Added,
+                    n.start,
+                    n.end,
+                    None,
+                    Some(n.data.clone()),
+                );
+                new = next_new(&mut report, &mut countries)?;
+                change
+            }
+            (Some(o), Some(n)) if o.start < n.start => {
+                let end = o.end.min(n.start - 1);
+                let change = (
+                    ChangeKind::Removed,
+                    o.start,
+                    end,
+                    Some(o.data.clone()),
+                    None,
+                );
+                match end == o.end {
+                    true => old = next_old(&mut report, &mut countries)?,
+                    false => o.start = end + 1,
+                }
+                change
+            }
+            (Some(o), Some(n)) if n.start < o.start => {
+                let end = n.end.min(o.start - 1);
+                let change = (ChangeKind::Added, n.start, end, None, Some(n.data.clone()));
+                match end == n.end {
+                    true => new = next_new(&mut report, &mut countries)?,
+                    false => n.start = end + 1,
+                }
+                change
+            }
+            (Some(o), Some(n)) => {
+                let end = o.end.min(n.end);
+                let change = (
+                    ChangeKind::Changed,
+                    o.start,
+                    end,
+                    Some(o.data.clone()),
+                    Some(n.data.clone()),
+                );
+                match end == o.end {
+                    true => old = next_old(&mut report, &mut countries)?,
+                    false => o.start = end + 1,
+                }
+                match end == n.end {
+                    true => new = next_new(&mut report, &mut countries)?,
+                    false => n.start = end + 1,
+                }
+                change
+            }
+        };
+
+        let (change, start, end, old_data, new_data) = change;
+        if change == ChangeKind::Changed && old_data == new_data {
+            continue;
+        }
+
+        // Adjacent ranges with the same change are reported as one, e.g. a network split into
+        // two networks with the same data is unchanged
+        if let Some(pending) = &mut pending
+            && pending.change == change
+            && pending.end.checked_add(1) == Some(start)
+            && pending.old == old_data
+            && pending.new == new_data
+        {
+            pending.end = end;
+            continue;
+        }
+
+        if let Some(pending) = pending.take() {
+            add_change(&mut report, &mut countries, &pending);
+        }
+        pending = Some(Pending {
+            change,
+            start,
+            end,
+            old: old_data,
+            new: new_data,
+        });
+    }
+
+    if let Some(pending) = pending {
+        add_change(&mut report, &mut countries, &pending);
+    }
+
+    report.countries = countries.into_values().collect();
+
+    Ok(report)
+}
+
+impl DiffReport {
+    pub fn write(&self, format: DiffFormat, writer: impl Write) -> Result<(), Box<dyn Error>> {
+        match format {
+            DiffFormat::Json => serde_json::to_writer_pretty(writer, self)?,
+            DiffFormat::Csv => write_csv(writer, &self.networks)?,
+            DiffFormat::CountryCsv => write_csv(writer, &self.countries)?,
+        }
+
+        Ok(())
+    }
+}
+
+fn write_csv<T: Serialize>(writer: impl Write, rows: &[T]) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn summary(path: &Path, reader: &Reader<Vec<u8>>) -> DbSummary {
+    DbSummary {
+        path: path.display().to_string(),
+        database_type: reader.metadata.database_type.clone(),
+        build_epoch: reader.metadata.build_epoch,
+        networks: 0,
+    }
+}
+
+fn country_counts<'a>(
+    countries: &'a mut BTreeMap<String, CountryCounts>,
+    country: &str,
+) -> &'a mut CountryCounts {
+    countries
+        .entry(country.to_string())
+        .or_insert_with(|| CountryCounts {
+            country: country.to_string(),
+            ..CountryCounts::default()
+        })
+}
+
+/// Iterates the networks of the database in ascending order
+fn ranges(
+    reader: &Reader<Vec<u8>>,
+) -> Result<impl Iterator<Item = Result<Range, MaxMindDbError>>, MaxMindDbError> {
+    // Many networks share the same record, which is decoded only once
+    let mut records: HashMap<usize, Rc<NetworkData>> = HashMap::new();
+
+    Ok(reader
+        .networks(WithinOptions::default())?
+        .map(move |result| {
+            let result = result?;
+            let network = result.network()?;
+
+            let data = match result.offset() {
+                Some(offset) => match records.get(&offset) {
+                    Some(data) => data.clone(),
+                    None => {
+                        let data = Rc::new(result.decode::<NetworkData>()?.unwrap_or_default());
+                        records.insert(offset, data.clone());
+                        data
+                    }
+                },
+                None => Rc::new(NetworkData::default()),
+            };
+
+            let (start, end) = match network.network() {
+                IpAddr::V4(ip) => {
+                    let host_mask = u32::MAX.checked_shr(network.prefix().into()).unwrap_or(0);
+                    let start = u32::from(ip);
+                    (start as u128, (start | host_mask) as u128)
+                }
+                IpAddr::V6(ip) => {
+                    let host_mask = u128::MAX.checked_shr(network.prefix().into()).unwrap_or(0);
+                    let start = u128::from(ip);
+                    (start, start | host_mask)
+                }
+            };
+
+            Ok(Range { start, end, data })
+        }))
+}
+
+fn add_change(
+    report: &mut DiffReport,
+    countries: &mut BTreeMap<String, CountryCounts>,
+    pending: &Pending,
+) {
+    let old_country = pending.old.as_ref().and_then(|data| data.country());
+    let new_country = pending.new.as_ref().and_then(|data| data.country());
+
+    for network in cidrs(pending.start, pending.end) {
+        match pending.change {
+            ChangeKind::Changed => report.changed += 1,
+            ChangeKind::Added => report.added += 1,
+            ChangeKind::Removed => report.removed += 1,
+        }
+
+        if old_country != new_country {
+            if let Some(country) = old_country {
+                country_counts(countries, country).lost += 1;
+            }
+            if let Some(country) = new_country {
+                country_counts(countries, country).gained += 1;
+            }
+        }
+
+        report.networks.push(NetworkChange {
+            change: pending.change,
+            network,
+            old_country: old_country.map(String::from),
+            new_country: new_country.map(String::from),
+            old_asn: pending
+                .old
+                .as_ref()
+                .and_then(|d| d.autonomous_system_number),
+            new_asn: pending
+                .new
+                .as_ref()
+                .and_then(|d| d.autonomous_system_number),
+        });
+    }
+}
+
+/// Smallest set of networks covering the range. Ranges in `::/96` are IPv4 networks.
+fn cidrs(start: u128, end: u128) -> Vec<String> {
+    const IPV4_END: u128 = u32::MAX as u128;
+
+    let mut networks: Vec<String> = Vec::new();
+
+    if start <= IPV4_END {
+        networks.extend(
+            Ipv4Subnets::new(
+                Ipv4Addr::from(start as u32),
+                Ipv4Addr::from(end.min(IPV4_END) as u32),
+                0,
+            )
+            .map(|network| network.to_string()),
+        );
+    }
+
+    if end > IPV4_END {
+        networks.extend(
+            Ipv6Subnets::new(
+                Ipv6Addr::from(start.max(IPV4_END + 1)),
+                Ipv6Addr::from(end),
+                0,
+            )
+            .map(|network| network.to_string()),
+        );
+    }
+
+    networks
+}
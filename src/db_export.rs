@@ -0,0 +1,461 @@
+use crate::models::FlatLookupResult;
+
+use maxminddb::geoip2::{
+    AnonymousIp, Asn, City, ConnectionType, Country, DensityIncome, Enterprise, Isp,
+};
+use maxminddb::{LookupResult, MaxMindDbError, Reader, WithinOptions};
+use parquet::basic::Compression;
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Rows per Parquet row group
+const ROW_GROUP_SIZE: usize = 64 * 1024;
+
+/// Output format of an export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "csv" => Some(Self::Csv),
+            "jsonl" | "ndjson" => Some(Self::Jsonl),
+            "parquet" => Some(Self::Parquet),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FieldType {
+    String,
+    Double,
+    Integer,
+    Boolean,
+}
+
+/// Record of a database, decoded with the model of its MaxMind edition so that it is flattened
+/// like lookups of that type are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RecordType {
+    AnonymousIp,
+    Asn,
+    City,
+    ConnectionType,
+    Country,
+    DensityIncome,
+    Enterprise,
+    Isp,
+    /// Records of other vendors, flattened with the best effort mapping of raw records
+    Raw,
+}
+
+impl RecordType {
+    fn from_database_type(database_type: &str) -> Self {
+        let edition = database_type
+            .strip_prefix("GeoIP2-")
+            .or_else(|| database_type.strip_prefix("GeoLite2-"));
+
+        match edition {
+            Some("Anonymous-IP") => Self::AnonymousIp,
+            Some("ASN") => Self::Asn,
+            Some("Connection-Type") => Self::ConnectionType,
+            Some("DensityIncome") => Self::DensityIncome,
+            Some("Enterprise") => Self::Enterprise,
+            Some("ISP") => Self::Isp,
+            // Including regional editions, e.g. `GeoIP2-City-Europe`
+            Some(edition) if edition.starts_with("City") => Self::City,
+            Some(edition) if edition.starts_with("Country") => Self::Country,
+            _ => Self::Raw,
+        }
+    }
+
+    fn flatten(
+        self,
+        result: &LookupResult<'_, Vec<u8>>,
+    ) -> Result<FlatLookupResult, MaxMindDbError> {
+        fn decode<'de, T>(
+            result: &LookupResult<'de, Vec<u8>>,
+        ) -> Result<FlatLookupResult, MaxMindDbError>
+        where
+            T: serde::Deserialize<'de>,
+            for<'r> &'r T: Into<FlatLookupResult>,
+        {
+            Ok(result
+                .decode::<T>()?
+                .map(|record| (&record).into())
+                .unwrap_or_default())
+        }
+
+        match self {
+            Self::AnonymousIp => decode::<AnonymousIp>(result),
+            Self::Asn => decode::<Asn>(result),
+            Self::City => decode::<City>(result),
+            Self::ConnectionType => decode::<ConnectionType>(result),
+            Self::Country => decode::<Country>(result),
+            Self::DensityIncome => decode::<DensityIncome>(result),
+            Self::Enterprise => decode::<Enterprise>(result),
+            Self::Isp => decode::<Isp>(result),
+            Self::Raw => decode::<Value>(result),
+        }
+    }
+}
+
+/// Exportable fields, the fields of [`FlatLookupResult`]
+const FIELDS: [(&str, FieldType); 22] = [
+    ("continent_code", FieldType::String),
+    ("country_code", FieldType::String),
+    ("country_name", FieldType::String),
+    ("region_code", FieldType::String),
+    ("region", FieldType::String),
+    ("city", FieldType::String),
+    ("postal", FieldType::String),
+    ("lat", FieldType::Double),
+    ("lon", FieldType::Double),
+    ("accuracy_radius", FieldType::Integer),
+    ("timezone", FieldType::String),
+    ("asn", FieldType::Integer),
+    ("org", FieldType::String),
+    ("isp", FieldType::String),
+    ("connection_type", FieldType::String),
+    ("is_anonymous", FieldType::Boolean),
+    ("is_anonymous_vpn", FieldType::Boolean),
+    ("is_hosting_provider", FieldType::Boolean),
+    ("is_public_proxy", FieldType::Boolean),
+    ("is_residential_proxy", FieldType::Boolean),
+    ("is_tor_exit_node", FieldType::Boolean),
+    ("is_anycast", FieldType::Boolean),
+];
+
+/// Names of all exportable fields
+pub fn field_names() -> Vec<&'static str> {
+    FIELDS.iter().map(|(name, _)| *name).collect()
+}
+
+/// Parses a comma separated list of field names. Each field may be given once, as the columns of
+/// the output are named by them.
+pub fn parse_fields(fields: &str) -> Result<Vec<&'static str>, Box<dyn Error>> {
+    let mut parsed = Vec::new();
+
+    for field in fields.split(',').map(str::trim) {
+        if field.is_empty() {
+            continue;
+        }
+
+        let name = FIELDS
+            .iter()
+            .find(|(name, _)| *name == field)
+            .map(|(name, _)| *name)
+            .ok_or_else(|| format!("Unknown field {field:?}"))?;
+
+        if parsed.contains(&name) {
+            return Err(format!("Duplicate field {field:?}").into());
+        }
+        parsed.push(name);
+    }
+
+    Ok(parsed)
+}
+
+/// Writes the network and `fields` of every network of the database in `format`. Returns the
+/// number of exported networks.
+///
+/// Records of MaxMind editions are flattened like lookups of their type, those of other vendors
+/// like raw lookups.
+pub fn export(
+    reader: &Reader<Vec<u8>>,
+    fields: &[&str],
+    format: ExportFormat,
+    writer: impl Write + Send,
+) -> Result<u64, Box<dyn Error>> {
+    let mut output = match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            writer.write_record(std::iter::once("network").chain(fields.iter().copied()))?;
+            Output::Csv(writer)
+        }
+        ExportFormat::Jsonl => Output::Jsonl(writer),
+        ExportFormat::Parquet => Output::Parquet(ParquetOutput::new(writer, fields)?),
+    };
+
+    let record_type = RecordType::from_database_type(&reader.metadata.database_type);
+
+    // Many networks share the same record, which is decoded only once
+    let mut rows: HashMap<usize, Rc<Vec<Value>>> = HashMap::new();
+    let mut count = 0;
+
+    for result in reader.networks(WithinOptions::default())? {
+        let result = result?;
+        let network = result.network()?.to_string();
+
+        let Some(offset) = result.offset() else {
+            continue;
+        };
+        let row = match rows.get(&offset) {
+            Some(row) => row.clone(),
+            None => {
+                let row = Rc::new(select(&record_type.flatten(&result)?, fields)?);
+                rows.insert(offset, row.clone());
+                row
+            }
+        };
+
+        output.write(&network, fields, &row)?;
+        count += 1;
+    }
+
+    output.finish()?;
+
+    Ok(count)
+}
+
+fn select(result: &FlatLookupResult, fields: &[&str]) -> Result<Vec<Value>, Box<dyn Error>> {
+    let Value::Object(mut result) = serde_json::to_value(result)? else {
+        return Err("Lookup result is not an object".into());
+    };
+
+    Ok(fields
+        .iter()
+        .map(|field| result.remove(*field).unwrap_or_default())
+        .collect())
+}
+
+enum Output<W: Write + Send> {
+    Csv(csv::Writer<W>),
+    Jsonl(W),
+    Parquet(ParquetOutput<W>),
+}
+
+impl<W: Write + Send> Output<W> {
+    fn write(
+        &mut self,
+        network: &str,
+        fields: &[&str],
+        row: &[Value],
+    ) -> Result<(), Box<dyn Error>> {
+        match self {
+            Self::Csv(writer) => {
+                writer.write_field(network)?;
+                for value in row {
+                    match value {
+                        Value::Null => writer.write_field("")?,
+                        Value::String(value) => writer.write_field(value)?,
+                        value => writer.write_field(value.to_string())?,
+                    }
+                }
+                writer.write_record(None::<&[u8]>)?;
+            }
+            Self::Jsonl(writer) => {
+                // Written field by field to keep the network first and the fields in order
+                write!(writer, "{{\"network\":")?;
+                serde_json::to_writer(&mut *writer, network)?;
+                for (field, value) in fields.iter().zip(row) {
+                    write!(writer, ",\"{field}\":")?;
+                    serde_json::to_writer(&mut *writer, value)?;
+                }
+                writer.write_all(b"}\n")?;
+            }
+            Self::Parquet(output) => output.write(network, row)?,
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Self::Csv(mut writer) => writer.flush()?,
+            Self::Jsonl(mut writer) => writer.flush()?,
+            Self::Parquet(output) => output.finish()?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Values of a column of the current row group. Missing values only have a definition level of
+/// `0`.
+struct Column {
+    values: ColumnValues,
+    levels: Vec<i16>,
+}
+
+enum ColumnValues {
+    String(Vec<ByteArray>),
+    Double(Vec<f64>),
+    Integer(Vec<i64>),
+    Boolean(Vec<bool>),
+}
+
+impl Column {
+    fn new(field_type: FieldType) -> Self {
+        let values = match field_type {
+            FieldType::String => ColumnValues::String(Vec::new()),
+            FieldType::Double => ColumnValues::Double(Vec::new()),
+            FieldType::Integer => ColumnValues::Integer(Vec::new()),
+            FieldType::Boolean => ColumnValues::Boolean(Vec::new()),
+        };
+
+        Self {
+            values,
+            levels: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, value: &Value) {
+        let present = match (&mut self.values, value) {
+            (ColumnValues::String(values), Value::String(value)) => {
+                values.push(ByteArray::from(value.as_str()));
+                true
+            }
+            (ColumnValues::Double(values), Value::Number(value)) => {
+                value.as_f64().map(|value| values.push(value)).is_some()
+            }
+            (ColumnValues::Integer(values), Value::Number(value)) => {
+                value.as_i64().map(|value| values.push(value)).is_some()
+            }
+            (ColumnValues::Boolean(values), Value::Bool(value)) => {
+                values.push(*value);
+                true
+            }
+            _ => false,
+        };
+
+        self.levels.push(i16::from(present));
+    }
+
+    fn clear(&mut self) {
+        match &mut self.values {
+            ColumnValues::String(values) => values.clear(),
+            ColumnValues::Double(values) => values.clear(),
+            ColumnValues::Integer(values) => values.clear(),
+            ColumnValues::Boolean(values) => values.clear(),
+        }
+        self.levels.clear();
+    }
+}
+
+struct ParquetOutput<W: Write + Send> {
+    writer: SerializedFileWriter<W>,
+    networks: Vec<ByteArray>,
+    columns: Vec<Column>,
+}
+
+impl<W: Write + Send> ParquetOutput<W> {
+    fn new(writer: W, fields: &[&str]) -> Result<Self, Box<dyn Error>> {
+        let field_types: Vec<FieldType> = fields
+            .iter()
+            .map(|field| {
+                FIELDS
+                    .iter()
+                    .find(|(name, _)| name == field)
+                    .map(|(_, field_type)| *field_type)
+                    .ok_or_else(|| format!("Unknown field {field:?}"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let columns: String = fields
+            .iter()
+            .zip(&field_types)
+            .map(|(field, field_type)| match field_type {
+                FieldType::String => format!("optional binary {field} (STRING);"),
+                FieldType::Double => format!("optional double {field};"),
+                FieldType::Integer => format!("optional int64 {field};"),
+                FieldType::Boolean => format!("optional boolean {field};"),
+            })
+            .collect();
+        let schema = parse_message_type(&format!(
+            "message network {{ required binary network (STRING); {columns} }}"
+        ))?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::GZIP(Default::default()))
+            .build();
+
+        Ok(Self {
+            writer: SerializedFileWriter::new(writer, Arc::new(schema), Arc::new(properties))?,
+            networks: Vec::new(),
+            columns: field_types.into_iter().map(Column::new).collect(),
+        })
+    }
+
+    fn write(&mut self, network: &str, row: &[Value]) -> Result<(), Box<dyn Error>> {
+        self.networks.push(ByteArray::from(network));
+        for (column, value) in self.columns.iter_mut().zip(row) {
+            column.push(value);
+        }
+
+        if self.networks.len() >= ROW_GROUP_SIZE {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the buffered rows as a row group
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.networks.is_empty() {
+            return Ok(());
+        }
+
+        let mut row_group = self.writer.next_row_group()?;
+
+        let mut network_column = row_group.next_column()?.ok_or("Missing network column")?;
+        network_column
+            .typed::<ByteArrayType>()
+            .write_batch(&self.networks, None, None)?;
+        network_column.close()?;
+
+        for column in &mut self.columns {
+            let mut writer = row_group.next_column()?.ok_or("Missing column")?;
+            let levels = Some(column.levels.as_slice());
+            match &column.values {
+                ColumnValues::String(values) => {
+                    writer
+                        .typed::<ByteArrayType>()
+                        .write_batch(values, levels, None)?;
+                }
+                ColumnValues::Double(values) => {
+                    writer
+                        .typed::<DoubleType>()
+                        .write_batch(values, levels, None)?;
+                }
+                ColumnValues::Integer(values) => {
+                    writer
+                        .typed::<Int64Type>()
+                        .write_batch(values, levels, None)?;
+                }
+                ColumnValues::Boolean(values) => {
+                    writer
+                        .typed::<BoolType>()
+                        .write_batch(values, levels, None)?;
+                }
+            }
+            writer.close()?;
+            column.clear();
+        }
+
+        row_group.close()?;
+        self.networks.clear();
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), Box<dyn Error>> {
+        self.flush()?;
+        self.writer.close()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Advisory lock of an edition in the database path, held while its databases are downloaded or
+/// removed. Lets processes sharing the database path (e.g. pods on a shared volume) update one at
+/// a time. Released on drop.
+#[derive(Debug)]
+pub struct UpdateLock {
+    _file: File,
+}
+
+impl UpdateLock {
+    /// Path of the lock file of `variant`
+    pub fn path(base_path: &str, variant: &str) -> PathBuf {
+        Path::new(base_path).join(format!("{variant}.lock"))
+    }
+
+    /// Waits until no other process holds the lock of `variant` and acquires it
+    pub async fn acquire(base_path: &str, variant: &str) -> io::Result<Self> {
+        let path = Self::path(base_path, variant);
+
+        tokio::task::spawn_blocking(move || {
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)?;
+
+            if let Err(TryLockError::WouldBlock) = file.try_lock() {
+                println!("Waiting for another process updating the database...");
+                file.lock()?;
+            }
+
+            Ok(Self { _file: file })
+        })
+        .await?
+    }
+}
+
+/// Shared lock on a loaded database file, telling other processes that its directory is in use
+/// and must not be removed. Released on drop.
+#[derive(Debug)]
+pub struct UsageLock {
+    _file: File,
+}
+
+impl UsageLock {
+    pub fn acquire(db_file: &Path) -> io::Result<Self> {
+        let file = File::open(db_file)?;
+        file.lock_shared()?;
+
+        Ok(Self { _file: file })
+    }
+
+    /// Whether another process holds a usage lock on `db_file`
+    pub fn is_in_use(db_file: &Path) -> io::Result<bool> {
+        let file = match File::open(db_file) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(error) => return Err(error),
+        };
+
+        match file.try_lock() {
+            Ok(()) => Ok(false),
+            Err(TryLockError::WouldBlock) => Ok(true),
+            Err(TryLockError::Error(error)) => Err(error),
+        }
+    }
+}
@@ -1,8 +1,14 @@
+use crate::download_utils::backoff_with_jitter;
 use actix_web::web;
+use chrono::{DateTime, Utc};
 use std::error::Error;
 use std::future::Future;
+use std::str::FromStr;
 use tokio::time::{Duration, sleep};
 
+/// Failed updates are retried sooner, backing off up to the next regular update
+const FAILURE_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
 pub trait UpdatableDB: Send + Sync {
     fn update_db(
         &self,
@@ -10,19 +16,146 @@ pub trait UpdatableDB: Send + Sync {
     ) -> impl Future<Output = Result<(), Box<dyn Error>>> + Send;
 }
 
-pub async fn start_db_update_daemon(data: web::Data<impl UpdatableDB + 'static>, interval: u64) {
-    let success_update_sleep = Duration::from_secs(interval);
-    let failure_update_sleep = Duration::from_secs(5 * 60);
+/// When to check for database updates
+#[derive(Debug, Clone)]
+pub enum UpdateSchedule {
+    /// Fixed delay between checks
+    Interval(Duration),
+    /// Checks at the times of a cron schedule (in UTC), each delayed by a random duration of up to
+    /// `jitter` so that replicas don't all download at once
+    Cron {
+        schedule: Box<cron::Schedule>,
+        jitter: Duration,
+    },
+}
+
+impl UpdateSchedule {
+    /// Parses a standard 5 field cron expression (`minute hour day-of-month month day-of-week`),
+    /// e.g. `0 6 * * TUE,FRI`. Days of the week are names or numbers from `0` (Sunday) to `7`
+    /// (Sunday). Expressions which never match, like `0 0 30 2 *`, are refused.
+    pub fn cron(expression: &str, jitter: Duration) -> Result<Self, Box<dyn Error>> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day_of_month, month, day_of_week] = fields[..] else {
+            return Err(format!(
+                "Invalid cron expression `{expression}`: expected 5 fields, got {}",
+                fields.len()
+            )
+            .into());
+        };
+
+        let schedule = cron::Schedule::from_str(&format!(
+            "0 {minute} {hour} {day_of_month} {month} {}",
+            day_names(day_of_week)
+        ))
+        .map_err(|error| format!("Invalid cron expression `{expression}`: {error}"))?;
+
+        if schedule.upcoming(Utc).next().is_none() {
+            return Err(format!("Invalid cron expression `{expression}`: never matches").into());
+        }
+
+        Ok(Self::Cron {
+            schedule: Box::new(schedule),
+            jitter,
+        })
+    }
+
+    /// Delay from `now` until the next check
+    pub fn next_delay(&self, now: DateTime<Utc>) -> Duration {
+        match self {
+            Self::Interval(interval) => *interval,
+            Self::Cron { schedule, jitter } => {
+                until_next(schedule, now).saturating_add(jitter.mul_f64(rand::random::<f64>()))
+            }
+        }
+    }
+
+    /// Delay from `now` until retrying after `failures` consecutive failed checks
+    pub fn retry_delay(&self, failures: u32, now: DateTime<Utc>) -> Duration {
+        let max = match self {
+            Self::Interval(interval) => *interval,
+            Self::Cron { schedule, .. } => until_next(schedule, now),
+        };
+
+        backoff_with_jitter(FAILURE_RETRY_DELAY.min(max), max, failures)
+    }
+}
 
+fn until_next(schedule: &cron::Schedule, now: DateTime<Utc>) -> Duration {
+    schedule
+        .after(&now)
+        .next()
+        .and_then(|next| (next - now).to_std().ok())
+        .unwrap_or(Duration::MAX)
+}
+
+/// Replaces the day-of-week numbers of standard cron, which start with Sunday as `0`, with names.
+/// The cron crate numbers days from Sunday as `1`.
+fn day_names(day_of_week: &str) -> String {
+    const DAYS: [&str; 8] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT", "SUN"];
+
+    day_of_week
+        .split(',')
+        .map(|item| {
+            let (range, step) = match item.split_once('/') {
+                Some((range, step)) => (range, Some(step)),
+                None => (item, None),
+            };
+
+            // Ranges ending on Sunday as `7` would wrap around the week, so they are listed out
+            let bounds = range.split_once('-').and_then(|(first, last)| {
+                Some((first.parse::<usize>().ok()?, last.parse::<usize>().ok()?))
+            });
+            let step_size = step.map_or(Some(1), |step| step.parse::<usize>().ok());
+            if let Some((first, last)) = bounds
+                && let Some(step_size) = step_size.filter(|&step_size| step_size > 0)
+                && first <= last
+                && last < DAYS.len()
+            {
+                return (first..=last)
+                    .step_by(step_size)
+                    .map(|day| DAYS[day])
+                    .collect::<Vec<_>>()
+                    .join(",");
+            }
+
+            let range = range
+                .split('-')
+                .map(|day| match day.parse::<usize>() {
+                    Ok(day) if day < DAYS.len() => DAYS[day].to_string(),
+                    _ => day.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("-");
+
+            match step {
+                Some(step) => format!("{range}/{step}"),
+                None => range,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+pub async fn start_db_update_daemon(
+    data: web::Data<impl UpdatableDB + 'static>,
+    schedule: UpdateSchedule,
+    db_min_age_secs: u64,
+) {
     tokio::spawn(async move {
+        let mut failures = 0;
+
         loop {
             println!("Checking for database updates...");
 
-            let duration = match data.update_db(interval).await {
-                Ok(_) => success_update_sleep,
+            let duration = match data.update_db(db_min_age_secs).await {
+                Ok(_) => {
+                    failures = 0;
+                    schedule.next_delay(Utc::now())
+                }
                 Err(error) => {
                     println!("Failed to update database {error:?}");
-                    failure_update_sleep
+                    failures += 1;
+                    schedule.retry_delay(failures, Utc::now())
                 }
             };
 
@@ -0,0 +1,42 @@
+use crate::maxmind_db::MaxmindDB;
+use actix_web::web;
+use notify::{EventKind, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use tokio::time::{Duration, timeout};
+
+/// Watches the database path and reloads the database once a newer versioned directory or a
+/// replaced `.mmdb` file appears, e.g. when updated by `geoipupdate`.
+///
+/// Changes are debounced until no new events arrive for `debounce`, so that files which are still
+/// being written are not loaded. Databases which fail to load are ignored and the current one is
+/// kept.
+pub async fn watch_db_path(data: web::Data<MaxmindDB>, debounce: Duration) {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+            Ok(_) => {
+                let _ = sender.send(());
+            }
+            Err(error) => println!("Database watcher error {error:?}"),
+        })
+        .expect("Failed to create database watcher");
+
+    watcher
+        .watch(data.base_path().as_ref(), RecursiveMode::Recursive)
+        .expect("Failed to watch database path");
+
+    println!("Watching {} for database changes", data.base_path());
+
+    while receiver.recv().await.is_some() {
+        // Wait for the changes to settle
+        while let Ok(Some(())) = timeout(debounce, receiver.recv()).await {}
+
+        match data.reload_from_disk().await {
+            Ok(true) => println!("Database reloaded after a change in {}", data.base_path()),
+            Ok(false) => {}
+            Err(error) => println!("Failed to reload changed database {error:?}"),
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::{env, error::Error};
+
+/// Settings of a `geoipupdate` configuration file (e.g. `/etc/GeoIP.conf`), so that Atlas can
+/// share the configuration of an existing `geoipupdate` setup.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GeoIpConf {
+    pub account_id: Option<String>,
+    pub license_key: Option<String>,
+    pub edition_ids: Vec<String>,
+    pub database_directory: Option<String>,
+    pub host: Option<String>,
+    pub proxy: Option<String>,
+    pub proxy_user_password: Option<String>,
+}
+
+impl GeoIpConf {
+    /// Loads the configuration file at `GEOIP_CONF_PATH` when set.
+    pub fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        let Ok(path) = env::var("GEOIP_CONF_PATH") else {
+            return Ok(None);
+        };
+
+        let content = std::fs::read_to_string(&path)
+            .map_err(|error| format!("Failed to read {path}: {error}"))?;
+
+        Ok(Some(Self::parse(&content)?))
+    }
+
+    /// Parses the `Key value` lines of the configuration. Lines starting with `#` and unknown keys
+    /// are ignored. Like in `geoipupdate`, a `#` after the start of a line is part of the value.
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut conf = Self::default();
+
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once(char::is_whitespace) else {
+                return Err(format!("Invalid GeoIP.conf line {}: {line:?}", number + 1));
+            };
+            let value = value.trim().to_string();
+
+            match key {
+                // `UserId` and `ProductIds` are the names used by old geoipupdate versions
+                "AccountID" | "UserId" => conf.account_id = Some(value),
+                "LicenseKey" => conf.license_key = Some(value),
+                "EditionIDs" | "ProductIds" => {
+                    conf.edition_ids = value.split_whitespace().map(str::to_string).collect()
+                }
+                "DatabaseDirectory" => conf.database_directory = Some(value),
+                "Host" => conf.host = Some(value),
+                "Proxy" => conf.proxy = Some(value),
+                "ProxyUserPassword" => conf.proxy_user_password = Some(value),
+                _ => {}
+            }
+        }
+
+        Ok(conf)
+    }
+
+    /// Download URL of the MaxMind API on the configured host
+    pub fn download_url(&self) -> Option<String> {
+        let host = self.host.as_deref()?.trim_end_matches('/');
+        let base_url = if host.contains("://") {
+            host.to_string()
+        } else {
+            format!("https://{host}")
+        };
+
+        Some(format!(
+            "{base_url}/geoip/databases/{{VARIANT}}/download?suffix=tar.gz"
+        ))
+    }
+
+    /// Proxy URL including the credentials, if any
+    pub fn proxy_url(&self) -> Option<String> {
+        let proxy = self.proxy.as_deref()?;
+        let (scheme, address) = proxy.split_once("://").unwrap_or(("http", proxy));
+
+        Some(match &self.proxy_user_password {
+            Some(user_password) => {
+                let credentials = match user_password.split_once(':') {
+                    Some((user, password)) => {
+                        format!("{}:{}", percent_encode(user), percent_encode(password))
+                    }
+                    None => percent_encode(user_password),
+                };
+
+                format!("{scheme}://{credentials}@{address}")
+            }
+            None => format!("{scheme}://{address}"),
+        })
+    }
+}
+
+/// Percent-encodes all but the unreserved characters of `value`, e.g. for the credentials of a URL
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
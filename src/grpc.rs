@@ -0,0 +1,448 @@
+use crate::auth::{API_KEY_HEADER, ApiKeyStore, AuthenticatedKey, Rejection};
+use crate::maxmind_db::MaxmindDB;
+use crate::models::LookupResult;
+use crate::network_utils::SpecialIPCheck;
+use crate::rate_limit::{ClientKey, LimitExceeded, RateLimiter};
+use crate::services::lookup::MAX_IPS_PER_LOOKUP;
+use crate::tls::TlsConfig;
+
+use actix_web::web;
+use futures_util::{Stream, StreamExt};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::metadata::MetadataValue;
+use tonic::service::Interceptor;
+use tonic::service::interceptor::InterceptedService;
+use tonic::{Request, Response, Status, Streaming};
+
+/// Time clients have to complete the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time to wait before accepting connections again after accepting one failed
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(500);
+
+pub mod proto {
+    tonic::include_proto!("atlas.v1");
+}
+
+use proto::geo_ip_server::{GeoIp, GeoIpServer};
+use proto::{
+    GetMetadataRequest, GetMetadataResponse, IpLookupResult, LookupError, LookupRequest,
+    LookupResponse, LookupType,
+};
+
+/// Settings of the gRPC server shared with the HTTP server
+#[derive(Default)]
+pub struct GrpcConfig {
+    pub api_keys: Option<web::Data<ApiKeyStore>>,
+    pub rate_limiter: Option<web::Data<RateLimiter>>,
+    pub tls: Option<TlsConfig>,
+}
+
+/// gRPC implementation of the GeoIP service, sharing the database with the HTTP server.
+#[derive(Clone)]
+pub struct GeoIpService {
+    maxmind_db: web::Data<MaxmindDB>,
+    api_keys: Option<web::Data<ApiKeyStore>>,
+    rate_limiter: Option<web::Data<RateLimiter>>,
+}
+
+impl GeoIpService {
+    pub fn new(maxmind_db: web::Data<MaxmindDB>) -> Self {
+        Self {
+            maxmind_db,
+            api_keys: None,
+            rate_limiter: None,
+        }
+    }
+
+    /// Requires one of the API keys in the `x-api-key` metadata of all calls
+    pub fn with_api_keys(mut self, api_keys: Option<web::Data<ApiKeyStore>>) -> Self {
+        self.api_keys = api_keys;
+        self
+    }
+
+    /// Limits lookups per client, like on the HTTP server. Every message of a lookup stream counts
+    /// as a lookup.
+    pub fn with_rate_limiter(mut self, rate_limiter: Option<web::Data<RateLimiter>>) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    pub fn into_server(self) -> InterceptedService<GeoIpServer<Self>, ApiKeyInterceptor> {
+        let interceptor = ApiKeyInterceptor {
+            api_keys: self.api_keys.clone(),
+        };
+
+        InterceptedService::new(GeoIpServer::new(self), interceptor)
+    }
+
+    async fn lookup_request(
+        &self,
+        request: LookupRequest,
+        client: &ClientKey,
+    ) -> Result<LookupResponse, Status> {
+        let lookup_type = match request.lookup_type() {
+            LookupType::Unspecified => {
+                return Err(Status::invalid_argument("lookup_type must be specified"));
+            }
+            LookupType::AnonymousIp => "anonymous_ip",
+            LookupType::Asn => "asn",
+            LookupType::City => "city",
+            LookupType::ConnectionType => "connection_type",
+            LookupType::Country => "country",
+            LookupType::DensityIncome => "density_income",
+            LookupType::Enterprise => "enterprise",
+            LookupType::Isp => "isp",
+            LookupType::Raw => "raw",
+        };
+
+        if request.ip_addresses.len() > MAX_IPS_PER_LOOKUP {
+            return Err(Status::invalid_argument("Too many IP Addresses"));
+        }
+
+        if let Some(rate_limiter) = &self.rate_limiter {
+            let ip_count = u32::try_from(request.ip_addresses.len()).unwrap_or(u32::MAX);
+            rate_limiter
+                .check(client.clone(), ip_count)
+                .map_err(|limit_exceeded| match limit_exceeded {
+                    LimitExceeded::RetryAfter(retry_after) => {
+                        resource_exhausted("Rate limit exceeded", retry_after)
+                    }
+                    LimitExceeded::TooManyIps { limit } => Status::resource_exhausted(format!(
+                        "Batch lookups are limited to {limit} IP Addresses per minute"
+                    )),
+                })?;
+        }
+
+        let ip_addresses = request
+            .ip_addresses
+            .iter()
+            .map(|ip_address| {
+                let ip: IpAddr = ip_address.trim().parse().map_err(|_| {
+                    Status::invalid_argument(format!("Invalid IP Address {ip_address:?}"))
+                })?;
+
+                if ip.is_special_ip() {
+                    return Err(Status::invalid_argument(format!(
+                        "IP Address is part of a special list and not allowed: {ip}"
+                    )));
+                }
+
+                Ok(ip)
+            })
+            .collect::<Result<Vec<IpAddr>, Status>>()?;
+
+        let db_inner = self.maxmind_db.db.read().await;
+
+        let results = db_inner
+            .lookup_by_type(lookup_type, ip_addresses.clone())
+            .await
+            .ok_or_else(|| Status::invalid_argument("invalid lookup_type"))?;
+
+        let results = if request.flat {
+            LookupResult::Flat(results.flatten())
+        } else {
+            results
+        };
+
+        let (results, overrides) = match self.maxmind_db.overlay() {
+            Some(overlay) => overlay
+                .apply(results)
+                .map_err(|e| Status::internal(format!("Failed to apply overlay: {e}")))?,
+            None => (results, BTreeMap::new()),
+        };
+
+        let serde_json::Value::Object(records) = serde_json::to_value(&results)
+            .map_err(|e| Status::internal(format!("Failed to encode results: {e}")))?
+        else {
+            return Err(Status::internal("Failed to encode results"));
+        };
+
+        let results = ip_addresses
+            .iter()
+            .map(|ip| {
+                // Cloned as the same IP Address may be looked up more than once
+                let record = match records.get(&ip.to_string()) {
+                    Some(serde_json::Value::Object(record)) => Some(json_to_struct(record.clone())),
+                    _ => None,
+                };
+
+                IpLookupResult {
+                    ip_address: ip.to_string(),
+                    record,
+                    overlay_network: overrides.get(ip).map(ToString::to_string),
+                }
+            })
+            .collect();
+
+        Ok(LookupResponse {
+            results,
+            database_build_epoch: db_inner.build_epoch(),
+            error: None,
+        })
+    }
+}
+
+/// Checks the `x-api-key` metadata of calls when API keys are configured
+#[derive(Clone)]
+pub struct ApiKeyInterceptor {
+    api_keys: Option<web::Data<ApiKeyStore>>,
+}
+
+impl Interceptor for ApiKeyInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let Some(api_keys) = &self.api_keys else {
+            return Ok(request);
+        };
+
+        let key = request
+            .metadata()
+            .get(API_KEY_HEADER)
+            .and_then(|key| key.to_str().ok());
+
+        let key = api_keys.authenticate(key).map_err(rejection_status)?;
+        request.extensions_mut().insert(key);
+
+        Ok(request)
+    }
+}
+
+fn rejection_status(rejection: Rejection) -> Status {
+    match rejection {
+        Rejection::Unauthorized => Status::unauthenticated("Missing or invalid API key"),
+        Rejection::RateLimited(retry_after) => {
+            resource_exhausted("Rate limit exceeded for API key", retry_after)
+        }
+        Rejection::QuotaExceeded(retry_after) => {
+            resource_exhausted("Daily quota exceeded for API key", retry_after)
+        }
+    }
+}
+
+/// Status of a rate limited call, with the seconds until it may be retried in its metadata
+fn resource_exhausted(message: &str, retry_after: Duration) -> Status {
+    let mut status = Status::resource_exhausted(message);
+
+    // Whole seconds like the HTTP Retry-After header, rounded up
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    status
+        .metadata_mut()
+        .insert("retry-after", MetadataValue::from(seconds));
+
+    status
+}
+
+/// Response to a request of a lookup stream which failed with `status`
+fn error_response(status: &Status) -> LookupResponse {
+    let retry_after = status
+        .metadata()
+        .get("retry-after")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+
+    LookupResponse {
+        error: Some(LookupError {
+            code: status.code().into(),
+            message: status.message().to_string(),
+            retry_after,
+        }),
+        ..LookupResponse::default()
+    }
+}
+
+/// The client a request is limited as, its API key or otherwise its IP Address
+fn request_client<T>(request: &Request<T>) -> ClientKey {
+    match request.extensions().get::<AuthenticatedKey>() {
+        Some(key) => ClientKey::ApiKey(key.0.clone()),
+        None => request.remote_addr().map_or(ClientKey::Unknown, |addr| {
+            ClientKey::from_ip(addr.ip().to_canonical())
+        }),
+    }
+}
+
+fn json_to_struct(object: serde_json::Map<String, serde_json::Value>) -> prost_types::Struct {
+    prost_types::Struct {
+        fields: object
+            .into_iter()
+            .map(|(key, value)| (key, json_to_value(value)))
+            .collect(),
+    }
+}
+
+fn json_to_value(value: serde_json::Value) -> prost_types::Value {
+    use prost_types::value::Kind;
+
+    let kind = match value {
+        serde_json::Value::Null => Kind::NullValue(prost_types::NullValue::NullValue.into()),
+        serde_json::Value::Bool(value) => Kind::BoolValue(value),
+        serde_json::Value::Number(value) => Kind::NumberValue(value.as_f64().unwrap_or_default()),
+        serde_json::Value::String(value) => Kind::StringValue(value),
+        serde_json::Value::Array(values) => Kind::ListValue(prost_types::ListValue {
+            values: values.into_iter().map(json_to_value).collect(),
+        }),
+        serde_json::Value::Object(object) => Kind::StructValue(json_to_struct(object)),
+    };
+
+    prost_types::Value { kind: Some(kind) }
+}
+
+type LookupResponseStream = Pin<Box<dyn Stream<Item = Result<LookupResponse, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl GeoIp for GeoIpService {
+    async fn lookup(
+        &self,
+        request: Request<LookupRequest>,
+    ) -> Result<Response<LookupResponse>, Status> {
+        let client = request_client(&request);
+
+        self.lookup_request(request.into_inner(), &client)
+            .await
+            .map(Response::new)
+    }
+
+    type LookupStreamStream = LookupResponseStream;
+
+    async fn lookup_stream(
+        &self,
+        request: Request<Streaming<LookupRequest>>,
+    ) -> Result<Response<Self::LookupStreamStream>, Status> {
+        let service = self.clone();
+        let client = request_client(&request);
+        let api_key = request.extensions().get::<AuthenticatedKey>().cloned();
+
+        // Every message is a request towards the limits of the API key, like a lookup over HTTP.
+        // The first one was already recorded when the call was authenticated.
+        let responses = request
+            .into_inner()
+            .enumerate()
+            .then(move |(index, request)| {
+                let service = service.clone();
+                let client = client.clone();
+                let api_key = api_key.clone();
+                async move {
+                    let request = request?;
+
+                    let response = async {
+                        if let (Some(api_keys), Some(api_key)) = (&service.api_keys, &api_key)
+                            && index > 0
+                        {
+                            api_keys.record_request(api_key).map_err(rejection_status)?;
+                        }
+
+                        service.lookup_request(request, &client).await
+                    }
+                    .await;
+
+                    // An error status would end the whole stream
+                    Ok(response.unwrap_or_else(|status| error_response(&status)))
+                }
+            });
+
+        Ok(Response::new(Box::pin(responses)))
+    }
+
+    async fn get_metadata(
+        &self,
+        _request: Request<GetMetadataRequest>,
+    ) -> Result<Response<GetMetadataResponse>, Status> {
+        let db_inner = self.maxmind_db.db.read().await;
+        let metadata = &db_inner.reader.metadata;
+
+        Ok(Response::new(GetMetadataResponse {
+            variant: self.maxmind_db.variant.clone(),
+            database_type: metadata.database_type.clone(),
+            build_epoch: metadata.build_epoch,
+            ip_version: metadata.ip_version.into(),
+            languages: metadata.languages.clone(),
+            description: metadata.description.get("en").cloned().unwrap_or_default(),
+            node_count: metadata.node_count,
+            record_size: metadata.record_size.into(),
+            binary_format_major_version: metadata.binary_format_major_version.into(),
+            binary_format_minor_version: metadata.binary_format_minor_version.into(),
+        }))
+    }
+}
+
+pub async fn start_grpc_server(
+    maxmind_db: web::Data<MaxmindDB>,
+    addr: SocketAddr,
+    config: GrpcConfig,
+) {
+    let listener = TcpListener::bind(addr)
+        .await
+        .expect("Cannot bind to specified gRPC port");
+
+    serve(maxmind_db, listener, config).await;
+}
+
+/// Serves the gRPC service on `listener`, over TLS when configured
+pub async fn serve(maxmind_db: web::Data<MaxmindDB>, listener: TcpListener, config: GrpcConfig) {
+    let service = GeoIpService::new(maxmind_db)
+        .with_api_keys(config.api_keys)
+        .with_rate_limiter(config.rate_limiter)
+        .into_server();
+    let server = tonic::transport::Server::builder().add_service(service);
+
+    match &config.tls {
+        Some(tls) => {
+            let (tls_config, resolver) = crate::load_tls_config(tls);
+            tokio::spawn(resolver.watch(tls.reload_interval));
+
+            server
+                .serve_with_incoming(tls_incoming(listener, tls_config))
+                .await
+        }
+        None => {
+            server
+                .serve_with_incoming(TcpListenerStream::new(listener))
+                .await
+        }
+    }
+    .expect("gRPC Server crashed");
+}
+
+/// Connections of `listener` after their TLS handshake. Handshakes run concurrently so that slow
+/// clients don't hold up others, failed ones are dropped.
+fn tls_incoming(
+    listener: TcpListener,
+    tls_config: rustls::ServerConfig,
+) -> ReceiverStream<std::io::Result<TlsStream<TcpStream>>> {
+    let acceptor = TlsAcceptor::from(Arc::new(tls_config));
+    let (sender, receiver) = tokio::sync::mpsc::channel(64);
+
+    tokio::spawn(async move {
+        while !sender.is_closed() {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                // E.g. out of file descriptors, which lasts until connections are closed
+                Err(error) => {
+                    println!("Failed to accept gRPC connection: {error}");
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            };
+
+            let acceptor = acceptor.clone();
+            let sender = sender.clone();
+
+            tokio::spawn(async move {
+                if let Ok(Ok(stream)) =
+                    tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await
+                {
+                    let _ = sender.send(Ok(stream)).await;
+                }
+            });
+        }
+    });
+
+    ReceiverStream::new(receiver)
+}
@@ -1,41 +1,318 @@
+pub mod admin;
 pub mod api_docs;
+pub mod auth;
+pub mod db_diff;
+pub mod db_export;
+pub mod db_lock;
 pub mod db_refresher;
+pub mod db_watcher;
 pub mod download_utils;
+pub mod geoip_conf;
+pub mod grpc;
 pub mod maxmind_db;
+pub mod mirror;
 pub mod models;
 pub mod network_utils;
+pub mod overlay;
+pub mod providers;
+pub mod rate_limit;
+pub mod s3;
 pub mod services;
+pub mod tls;
+pub mod webhooks;
 
 use std::error::Error;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
-use actix_web::{App, HttpServer, web};
-use maxmind_db::MaxmindDB;
+use actix_web::dev::Server;
+use actix_web::{App, HttpServer, middleware, web};
+use admin::AdminConfig;
+use auth::ApiKeyStore;
+use download_utils::backoff_with_jitter;
+use grpc::GrpcConfig;
+use maxmind_db::{DbLayout, MaxmindDB};
+use mirror::MirrorConfig;
+use network_utils::ClientIpConfig;
+use overlay::Overlay;
+use providers::DbProvider;
+use rate_limit::RateLimiter;
+use tls::{ReloadingCertResolver, TlsConfig};
 use utoipa_swagger_ui::SwaggerUi;
+use webhooks::Webhooks;
+
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    /// Whether to listen on `host` and `port`. Can be disabled when listening on a unix socket.
+    pub tcp_enabled: bool,
+    pub unix_socket: Option<UnixSocketConfig>,
+    pub swagger_ui_enabled: bool,
+    pub client_ip_config: ClientIpConfig,
+    /// Shared with the gRPC server, so that both count towards the same limits
+    pub api_keys: Option<web::Data<ApiKeyStore>>,
+    pub rate_limiter: Option<web::Data<RateLimiter>>,
+    pub tls: Option<TlsConfig>,
+    /// Serves the loaded database to other instances on `/mirror/{variant}` when set
+    pub mirror: Option<MirrorConfig>,
+    /// Serves the administrative endpoints under `/admin` when set
+    pub admin: Option<AdminConfig>,
+}
+
+pub struct UnixSocketConfig {
+    pub path: PathBuf,
+    /// File permissions of the socket, e.g. `0o660`
+    pub mode: Option<u32>,
+}
+
+#[cfg(unix)]
+impl UnixSocketConfig {
+    /// Removes a socket left behind by a previous run, which would otherwise make binding fail.
+    fn remove_stale_socket(&self) -> std::io::Result<()> {
+        use std::os::unix::fs::FileTypeExt;
+
+        match std::fs::symlink_metadata(&self.path) {
+            Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(&self.path),
+            _ => Ok(()),
+        }
+    }
+
+    /// Binds the socket. With a `mode`, it is bound in a private directory next to its path and
+    /// only moved into place once its permissions are set, so that no other user can connect
+    /// before.
+    fn bind(&self) -> std::io::Result<std::os::unix::net::UnixListener> {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+        use std::os::unix::net::UnixListener;
+
+        self.remove_stale_socket()?;
+
+        let Some(mode) = self.mode else {
+            return UnixListener::bind(&self.path);
+        };
+
+        let file_name = self
+            .path
+            .file_name()
+            .ok_or(std::io::ErrorKind::InvalidInput)?;
+        let parent = self
+            .path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let private_dir = parent.join(format!(
+            ".{}.{}",
+            file_name.to_string_lossy(),
+            std::process::id()
+        ));
+        let private_path = private_dir.join(file_name);
+
+        std::fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&private_dir)?;
+
+        let listener = UnixListener::bind(&private_path).and_then(|listener| {
+            std::fs::set_permissions(&private_path, std::fs::Permissions::from_mode(mode))?;
+            std::fs::rename(&private_path, &self.path)?;
+            Ok(listener)
+        });
+        let _ = std::fs::remove_dir_all(&private_dir);
+
+        listener
+    }
+}
 
 pub async fn init_db(
     db_path: &str,
     db_variant: &str,
 ) -> Result<web::Data<MaxmindDB>, Box<dyn Error>> {
-    let maxmind_db = MaxmindDB::init(db_variant, db_path).await?;
+    let maxmind_db = MaxmindDB::init(db_variant, db_path)
+        .await?
+        .with_webhooks(Webhooks::from_env()?)
+        .with_overlay(Overlay::from_env()?);
 
     Ok(web::Data::new(maxmind_db))
 }
 
-pub async fn start_db_refresher(maxmind_db_arc: web::Data<MaxmindDB>, update_interval: u64) {
-    db_refresher::start_db_update_daemon(maxmind_db_arc, update_interval).await;
+/// Loads the database, or the database at `fallback_path` when it cannot be loaded
+pub async fn init_db_with_fallback(
+    db_path: &str,
+    db_variant: &str,
+    fallback_path: &Path,
+) -> Result<web::Data<MaxmindDB>, Box<dyn Error>> {
+    let maxmind_db = MaxmindDB::init_with_fallback(
+        db_variant,
+        db_path,
+        DbProvider::from_env()?,
+        DbLayout::from_env()?,
+        fallback_path,
+    )
+    .await?
+    .with_webhooks(Webhooks::from_env()?)
+    .with_overlay(Overlay::from_env()?);
+
+    Ok(web::Data::new(maxmind_db))
+}
+
+/// Retries loading the database until it succeeds, backing off up to `max_backoff`
+pub async fn wait_for_db(
+    db_path: &str,
+    db_variant: &str,
+    max_backoff: Duration,
+) -> web::Data<MaxmindDB> {
+    let mut attempt = 1;
+
+    loop {
+        let reason = match init_db(db_path, db_variant).await {
+            Ok(maxmind_db) => return maxmind_db,
+            Err(reason) => reason.to_string(),
+        };
+
+        let backoff = backoff_with_jitter(Duration::from_secs(5), max_backoff, attempt);
+        println!("Failed to load/initialize database, retrying in {backoff:?}: {reason}");
+
+        tokio::time::sleep(backoff).await;
+        attempt = attempt.saturating_add(1);
+    }
+}
+
+pub async fn start_db_refresher(
+    maxmind_db_arc: web::Data<MaxmindDB>,
+    schedule: db_refresher::UpdateSchedule,
+    db_min_age_secs: u64,
+) {
+    db_refresher::start_db_update_daemon(maxmind_db_arc, schedule, db_min_age_secs).await;
+}
+
+pub async fn start_db_watcher(maxmind_db_arc: web::Data<MaxmindDB>, debounce: Duration) {
+    db_watcher::watch_db_path(maxmind_db_arc, debounce).await;
 }
 
-pub async fn start_server(
+/// Reloads the overlay of the database whenever its file changes
+pub async fn start_overlay_watcher(maxmind_db_arc: web::Data<MaxmindDB>, interval: Duration) {
+    match maxmind_db_arc.overlay() {
+        Some(overlay) => overlay.clone().watch(interval).await,
+        None => std::future::pending().await,
+    }
+}
+
+pub async fn start_grpc_server(
     maxmind_db_arc: web::Data<MaxmindDB>,
-    host: &str,
-    port: u16,
-    swagger_ui_enabled: bool,
+    addr: SocketAddr,
+    config: GrpcConfig,
 ) {
+    grpc::start_grpc_server(maxmind_db_arc, addr, config).await;
+}
+
+/// Binds the TCP and unix socket listeners of the [`ServerConfig`] to the `HttpServer`. A macro
+/// rather than a function, as the `HttpServer` type depends on the app.
+macro_rules! bind_listeners {
+    ($server:ident, $config:expr, $tls_config:expr) => {
+        assert!(
+            $config.tcp_enabled || $config.unix_socket.is_some(),
+            "No listener configured, enable TCP or set a unix socket path"
+        );
+
+        if $config.tcp_enabled {
+            let addr = ($config.host.clone(), $config.port);
+            $server = match $tls_config {
+                Some(tls_config) => $server.bind_rustls_0_23(addr, tls_config),
+                None => $server.bind(addr),
+            }
+            .expect("Cannot bind to specified host and port");
+        }
+
+        #[cfg(unix)]
+        if let Some(unix_socket) = &$config.unix_socket {
+            let listener = unix_socket
+                .bind()
+                .expect("Cannot bind to specified unix socket");
+            $server = $server
+                .listen_uds(listener)
+                .expect("Cannot listen on specified unix socket");
+        }
+
+        #[cfg(not(unix))]
+        assert!(
+            $config.unix_socket.is_none(),
+            "Unix sockets are not supported on this platform"
+        );
+    };
+}
+
+pub(crate) fn load_tls_config(
+    tls: &TlsConfig,
+) -> (rustls::ServerConfig, Arc<ReloadingCertResolver>) {
+    let resolver = Arc::new(
+        ReloadingCertResolver::load(&tls.cert_path, &tls.key_path)
+            .expect("Failed to load TLS certificate"),
+    );
+    let tls_config = tls
+        .server_config(resolver.clone())
+        .expect("Invalid TLS configuration");
+
+    (tls_config, resolver)
+}
+
+/// Answers all requests with `503 Service Unavailable` on the listeners of `config`, e.g. while
+/// waiting for the database on startup. Must be stopped before the actual server is started.
+pub fn start_not_ready_server(config: &ServerConfig) -> Server {
+    let mut server = HttpServer::new(|| {
+        App::new().default_service(web::to(|| async {
+            services::service_unavailable(
+                "Database is not loaded yet".to_string(),
+                "NOT_READY".to_string(),
+            )
+        }))
+    });
+
+    let tls_config = config.tls.as_ref().map(|tls| load_tls_config(tls).0);
+
+    bind_listeners!(server, config, tls_config);
+
+    server.run()
+}
+
+pub async fn start_server(maxmind_db_arc: web::Data<MaxmindDB>, config: ServerConfig) {
+    let swagger_ui_enabled = config.swagger_ui_enabled;
+    let client_ip_config = web::Data::new(config.client_ip_config);
+    let api_keys = config.api_keys;
+    let rate_limiter = config.rate_limiter;
+    let mirror = config.mirror.map(web::Data::new);
+    let admin = config.admin.map(web::Data::new);
+
     // Start HTTP Server
-    HttpServer::new(move || {
+    let mut server = HttpServer::new(move || {
         let reader_data = maxmind_db_arc.clone();
-        let app = App::new()
+        let mut app = App::new()
             .app_data(reader_data)
+            .app_data(client_ip_config.clone());
+
+        if let Some(api_keys) = &api_keys {
+            app = app.app_data(api_keys.clone());
+        }
+
+        if let Some(rate_limiter) = &rate_limiter {
+            app = app.app_data(rate_limiter.clone());
+        }
+
+        if let Some(mirror) = &mirror {
+            app = app
+                .app_data(mirror.clone())
+                .service(services::mirror::handle);
+        }
+
+        if let Some(admin) = &admin {
+            app = app.app_data(admin.clone()).service(services::admin::diff);
+        }
+
+        let app = app
+            // Middlewares run in reverse order of registration, API keys are checked first
+            .wrap(middleware::from_fn(rate_limit::limit_lookups))
+            .wrap(middleware::from_fn(auth::require_api_key))
+            // `whoami` must be registered before `lookup` since its path also matches the lookup path
+            .service(services::whoami::handle)
             .service(services::lookup::handle)
             .service(services::healthcheck::handle);
 
@@ -48,10 +325,15 @@ pub async fn start_server(
         } else {
             app
         }
-    })
-    .bind((host, port))
-    .expect("Cannot bind to specified host and port")
-    .run()
-    .await
-    .expect("HTTP Server crashed");
+    });
+
+    let tls_config = config.tls.as_ref().map(|tls| {
+        let (tls_config, resolver) = load_tls_config(tls);
+        tokio::spawn(resolver.watch(tls.reload_interval));
+        tls_config
+    });
+
+    bind_listeners!(server, config, tls_config);
+
+    server.run().await.expect("HTTP Server crashed");
 }
@@ -1,46 +1,252 @@
 use std::env;
 use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+use std::time::Duration;
 
+use atlas_rs::admin::AdminConfig;
 use atlas_rs::api_docs;
+use atlas_rs::auth::ApiKeyStore;
+use atlas_rs::db_diff::{self, DiffFormat};
+use atlas_rs::db_export::{self, ExportFormat};
+use atlas_rs::db_refresher::UpdateSchedule;
+use atlas_rs::geoip_conf::GeoIpConf;
+use atlas_rs::maxmind_db::{self, DbLayout, MaxmindDB};
+use atlas_rs::mirror::MirrorConfig;
+use atlas_rs::network_utils::ClientIpConfig;
+use atlas_rs::rate_limit::RateLimiter;
+use atlas_rs::tls::TlsConfig;
 use tokio::io::AsyncWriteExt;
 
 const SPEC_FILENAME: &str = "openapi-spec.json";
 
 #[actix_web::main]
 async fn main() -> Result<()> {
-    let db_variant = env::var("MAXMIND_DB_VARIANT").unwrap_or("GeoLite2-City".to_string());
-    let db_path = env::var("DB_PATH").unwrap_or("/opt/atlas/db".to_string());
+    // Settings of an existing geoipupdate setup are used unless overridden by env vars
+    let geoip_conf = GeoIpConf::from_env()
+        .expect("Failed to load GEOIP_CONF_PATH")
+        .unwrap_or_default();
 
+    let db_variant = env::var("MAXMIND_DB_VARIANT")
+        .ok()
+        .or(geoip_conf.edition_ids.first().cloned())
+        .unwrap_or("GeoLite2-City".to_string());
+    let db_path = env::var("DB_PATH")
+        .ok()
+        .or(geoip_conf.database_directory)
+        .unwrap_or("/opt/atlas/db".to_string());
+
+    // Local databases are cheap to check for changes, downloads are not
+    let default_update_interval = match env::var("DB_PROVIDER").as_deref() {
+        Ok("local") => "60",
+        _ => "86400",
+    };
     let update_interval: u64 = env::var("DB_UPDATE_INTERVAL_SECONDS")
-        .unwrap_or("86400".to_string())
+        .unwrap_or(default_update_interval.to_string())
         .parse()
         .expect("Invalid DB_UPDATE_INTERVAL_SECONDS value");
 
+    let update_schedule = match env::var("DB_UPDATE_SCHEDULE") {
+        Ok(expression) => {
+            let jitter: u64 = env::var("DB_UPDATE_JITTER_SECONDS")
+                .unwrap_or("300".to_string())
+                .parse()
+                .expect("Invalid DB_UPDATE_JITTER_SECONDS value");
+
+            UpdateSchedule::cron(&expression, Duration::from_secs(jitter))
+                .expect("Invalid DB_UPDATE_SCHEDULE value")
+        }
+        Err(_) => UpdateSchedule::Interval(Duration::from_secs(update_interval)),
+    };
+
+    // Databases younger than this are not downloaded again
+    let db_min_age_secs: u64 = env::var("DB_MIN_AGE_SECONDS")
+        .unwrap_or(update_interval.to_string())
+        .parse()
+        .expect("Invalid DB_MIN_AGE_SECONDS value");
+
+    let db_startup_mode = env::var("DB_STARTUP_MODE").unwrap_or("fail".to_string());
+
+    let db_watch_enabled: bool = env::var("DB_WATCH_ENABLED")
+        .unwrap_or("false".to_string())
+        .parse()
+        .expect("Invalid DB_WATCH_ENABLED value. Expected `false` or `true`");
+
+    let db_watch_debounce = Duration::from_millis(
+        env::var("DB_WATCH_DEBOUNCE_MS")
+            .unwrap_or("2000".to_string())
+            .parse()
+            .expect("Invalid DB_WATCH_DEBOUNCE_MS value"),
+    );
+
     let host = env::var("HOST").unwrap_or("0.0.0.0".to_string());
     let port: u16 = env::var("PORT")
         .unwrap_or("8080".to_string())
         .parse()
         .expect("Invalid PORT value");
 
+    let tcp_enabled: bool = env::var("TCP_ENABLED")
+        .unwrap_or("true".to_string())
+        .parse()
+        .expect("Invalid TCP_ENABLED value. Expected `false` or `true`");
+
+    let unix_socket = env::var("UNIX_SOCKET_PATH")
+        .ok()
+        .map(|path| atlas_rs::UnixSocketConfig {
+            path: path.into(),
+            mode: env::var("UNIX_SOCKET_MODE").ok().map(|mode| {
+                u32::from_str_radix(&mode, 8)
+                    .expect("Invalid UNIX_SOCKET_MODE value. Expected an octal mode like `660`")
+            }),
+        });
+
     let swagger_ui_enabled: bool = env::var("SWAGGER_UI_ENABLED")
         .unwrap_or("false".to_string())
         .parse()
         .expect("Invalid SWAGGER_UI_ENABLED value. Expected `false` or `true`");
 
+    let client_ip_config = ClientIpConfig::parse(
+        &env::var("TRUSTED_PROXIES").unwrap_or_default(),
+        &env::var("CLIENT_IP_HEADERS").unwrap_or("X-Forwarded-For".to_string()),
+    )
+    .expect("Invalid TRUSTED_PROXIES or CLIENT_IP_HEADERS value");
+
+    let rate_limiter = RateLimiter::new(
+        env::var("RATE_LIMIT_PER_MINUTE")
+            .ok()
+            .map(|limit| limit.parse().expect("Invalid RATE_LIMIT_PER_MINUTE value")),
+        env::var("RATE_LIMIT_BATCH_IPS_PER_MINUTE")
+            .ok()
+            .map(|limit| {
+                limit
+                    .parse()
+                    .expect("Invalid RATE_LIMIT_BATCH_IPS_PER_MINUTE value")
+            }),
+    );
+
+    let grpc_port: Option<u16> = env::var("GRPC_PORT")
+        .ok()
+        .map(|port| port.parse().expect("Invalid GRPC_PORT value"));
+
+    let tls = match (env::var("TLS_CERT_PATH"), env::var("TLS_KEY_PATH")) {
+        (Ok(cert_path), Ok(key_path)) => Some(TlsConfig {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+            client_ca_path: env::var("TLS_CLIENT_CA_PATH").ok().map(Into::into),
+            reload_interval: Duration::from_secs(
+                env::var("TLS_RELOAD_INTERVAL_SECONDS")
+                    .unwrap_or("60".to_string())
+                    .parse()
+                    .expect("Invalid TLS_RELOAD_INTERVAL_SECONDS value"),
+            ),
+        }),
+        (Err(_), Err(_)) => None,
+        _ => panic!("Both TLS_CERT_PATH and TLS_KEY_PATH must be set to enable TLS"),
+    };
+
+    let overlay_reload_interval = Duration::from_secs(
+        env::var("OVERLAY_RELOAD_INTERVAL_SECONDS")
+            .unwrap_or("30".to_string())
+            .parse()
+            .expect("Invalid OVERLAY_RELOAD_INTERVAL_SECONDS value"),
+    );
+
+    let mirror = env::var("MIRROR_TOKEN").ok().map(MirrorConfig::new);
+    let admin = env::var("ADMIN_TOKEN").ok().map(AdminConfig::new);
+
     let subcommand = env::args().nth(1);
 
     match subcommand.as_deref() {
         Some("server") | None => {
+            let api_keys = ApiKeyStore::load(
+                env::var("API_KEYS").ok().as_deref(),
+                env::var("API_KEYS_FILE").ok().as_deref(),
+            )
+            .await
+            .expect("Failed to load API keys")
+            .map(actix_web::web::Data::new);
+            let rate_limiter = rate_limiter.map(actix_web::web::Data::new);
+            let grpc_config = atlas_rs::grpc::GrpcConfig {
+                api_keys: api_keys.clone(),
+                rate_limiter: rate_limiter.clone(),
+                tls: tls.clone(),
+            };
+
+            let server_config = atlas_rs::ServerConfig {
+                host: host.clone(),
+                port,
+                tcp_enabled,
+                unix_socket,
+                swagger_ui_enabled,
+                client_ip_config,
+                api_keys,
+                rate_limiter,
+                tls,
+                mirror,
+                admin,
+            };
+
             // Load or Initialize MaxMind database
-            let maxmind_db_arc = atlas_rs::init_db(&db_path, &db_variant)
-                .await
-                .expect("Failed to load/initialize database");
+            let maxmind_db_arc = match db_startup_mode.as_str() {
+                "fail" => atlas_rs::init_db(&db_path, &db_variant)
+                    .await
+                    .expect("Failed to load/initialize database"),
+                "wait" => {
+                    let not_ready_server = atlas_rs::start_not_ready_server(&server_config);
+                    let not_ready_handle = not_ready_server.handle();
+                    actix_web::rt::spawn(not_ready_server);
+
+                    let maxmind_db_arc = atlas_rs::wait_for_db(
+                        &db_path,
+                        &db_variant,
+                        Duration::from_secs(update_interval.min(5 * 60)),
+                    )
+                    .await;
+
+                    not_ready_handle.stop(true).await;
+                    maxmind_db_arc
+                }
+                "fallback" => {
+                    let fallback_path = env::var("DB_FALLBACK_PATH")
+                        .expect("DB_FALLBACK_PATH must be set for the fallback startup mode");
+
+                    atlas_rs::init_db_with_fallback(&db_path, &db_variant, fallback_path.as_ref())
+                        .await
+                        .expect("Failed to load/initialize database")
+                }
+                mode => panic!("Invalid DB_STARTUP_MODE: {mode}"),
+            };
+
+            let grpc_server = async {
+                match grpc_port {
+                    Some(grpc_port) => {
+                        let addr = format!("{host}:{grpc_port}")
+                            .parse()
+                            .expect("Invalid HOST or GRPC_PORT value");
+                        atlas_rs::start_grpc_server(maxmind_db_arc.clone(), addr, grpc_config).await
+                    }
+                    None => std::future::pending().await,
+                }
+            };
+
+            let db_watcher = async {
+                if db_watch_enabled {
+                    atlas_rs::start_db_watcher(maxmind_db_arc.clone(), db_watch_debounce).await
+                } else {
+                    std::future::pending().await
+                }
+            };
 
             tokio::select! {
                 // Start Database Updater Daemon
-                _ = atlas_rs::start_db_refresher(maxmind_db_arc.clone(), update_interval) => {}
+                _ = atlas_rs::start_db_refresher(maxmind_db_arc.clone(), update_schedule, db_min_age_secs) => {}
                 // Start Server
-                _ = atlas_rs::start_server(maxmind_db_arc, &host, port, swagger_ui_enabled) => {}
+                _ = atlas_rs::start_server(maxmind_db_arc.clone(), server_config) => {}
+                // Start gRPC Server
+                _ = grpc_server => {}
+                // Start Database Watcher
+                _ = db_watcher => {}
+                // Start Overlay Watcher
+                _ = atlas_rs::start_overlay_watcher(maxmind_db_arc.clone(), overlay_reload_interval) => {}
             }
 
             Ok(())
@@ -53,6 +259,91 @@ async fn main() -> Result<()> {
 
             Ok(())
         }
+        Some("diff") => {
+            let mut paths = Vec::new();
+            let mut format = DiffFormat::Json;
+            let mut args = env::args().skip(2);
+
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--format" => {
+                        let name = args.next().unwrap_or_default();
+                        format = DiffFormat::from_name(&name).ok_or(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Invalid format {name:?}. Expected json, csv or country-csv"),
+                        ))?;
+                    }
+                    _ => paths.push(arg),
+                }
+            }
+
+            let [old_path, new_path] = paths.as_slice() else {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Usage: atlas diff <old> <new> [--format json|csv|country-csv]",
+                ));
+            };
+
+            db_diff::diff(old_path.as_ref(), new_path.as_ref(), None)
+                .and_then(|report| report.write(format, std::io::stdout().lock()))
+                .map_err(|error| Error::other(format!("Failed to compare databases: {error}")))
+        }
+        Some("export") => {
+            let mut db_file_path = None;
+            let mut format = ExportFormat::Csv;
+            let mut fields = db_export::field_names();
+            let mut output_path = None;
+            let mut args = env::args().skip(2);
+
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--format" => {
+                        let name = args.next().unwrap_or_default();
+                        format = ExportFormat::from_name(&name).ok_or(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Invalid format {name:?}. Expected csv, jsonl or parquet"),
+                        ))?;
+                    }
+                    "--fields" => {
+                        fields = db_export::parse_fields(&args.next().unwrap_or_default())
+                            .map_err(|error| {
+                                Error::new(ErrorKind::InvalidInput, error.to_string())
+                            })?;
+                    }
+                    "--output" => output_path = args.next(),
+                    _ if db_file_path.is_none() => db_file_path = Some(arg),
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            "Usage: atlas export [<database>] [--format csv|jsonl|parquet] [--fields <field,...>] [--output <file>]",
+                        ));
+                    }
+                }
+            }
+
+            // Exports the given database file or otherwise the newest one in the database path,
+            // without initializing it like the server does (downloads, cleanups, webhooks)
+            let db_file_path = match db_file_path {
+                Some(path) => maxmind_db::db_file(path.as_ref(), None),
+                None => find_db_file(&db_path, &db_variant).await,
+            }
+            .map_err(|error| Error::other(format!("Failed to export database: {error}")))?;
+
+            let writer: Box<dyn std::io::Write + Send> = match &output_path {
+                Some(path) => Box::new(std::fs::File::create(path)?),
+                None => Box::new(std::io::stdout()),
+            };
+            let writer = std::io::BufWriter::new(writer);
+
+            let count = maxminddb::Reader::open_readfile(db_file_path)
+                .map_err(Into::into)
+                .and_then(|reader| db_export::export(&reader, &fields, format, writer))
+                .map_err(|error| Error::other(format!("Failed to export database: {error}")))?;
+
+            eprintln!("Exported {count} networks");
+
+            Ok(())
+        }
         Some("spec") => {
             let api_doc = api_docs::api_doc();
             let json_api_doc = api_doc.to_json().expect("Failed to generate API spec");
@@ -75,3 +366,15 @@ async fn main() -> Result<()> {
         )),
     }
 }
+
+/// Returns the `.mmdb` file of the newest local database of `variant` in `db_path`
+async fn find_db_file(
+    db_path: &str,
+    variant: &str,
+) -> std::result::Result<PathBuf, Box<dyn std::error::Error>> {
+    let db_dir = MaxmindDB::find_local_db(variant, db_path, DbLayout::from_env()?)
+        .await?
+        .ok_or_else(|| format!("No {variant} database found in {db_path}"))?;
+
+    maxmind_db::db_file(&db_dir, Some(variant))
+}
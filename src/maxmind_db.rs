@@ -1,27 +1,93 @@
 use crate::{
+    db_lock::{UpdateLock, UsageLock},
     db_refresher::UpdatableDB,
-    download_utils::{AlreadyDownloaded, download_with_basic_auth, extract_db},
+    download_utils::{AlreadyDownloaded, remove_orphaned_downloads, temp_path},
+    models::LookupResult,
+    overlay::Overlay,
+    providers::{DbProvider, Downloader},
+    webhooks::{UpdateEvent, Webhooks},
 };
 use maxminddb::{MaxMindDbError, Reader};
 use serde::Deserialize;
 use std::{
     collections::HashMap,
-    env,
     error::Error,
+    io,
     net::IpAddr,
     path::{Path, PathBuf},
+    sync::Arc,
+    time::{Instant, SystemTime},
 };
 use tokio::sync::RwLock;
 
-const MAXMIND_EXT: &str = "mmdb";
-const DEFAULT_DB_URL: &str =
-    "https://download.maxmind.com/geoip/databases/{VARIANT}/download?suffix=tar.gz";
+pub(crate) const MAXMIND_EXT: &str = "mmdb";
+
+/// Returns the `.mmdb` file at `path`. When it is a directory, the one of `variant` in it, with
+/// the variant taken from the name of versioned database directories when not given. Directories
+/// holding a single `.mmdb` file resolve to it regardless of its name.
+pub fn db_file(path: &Path, variant: Option<&str>) -> Result<PathBuf, Box<dyn Error>> {
+    if !path.is_dir() {
+        return Ok(path.to_path_buf());
+    }
+
+    let variant = variant.or_else(|| {
+        let (variant, _) = path.file_name()?.to_str()?.rsplit_once('_')?;
+        Some(variant)
+    });
+    if let Some(variant) = variant {
+        let file = path.join(format!("{variant}.{MAXMIND_EXT}"));
+        if file.is_file() {
+            return Ok(file);
+        }
+    }
+
+    let files: Vec<PathBuf> = std::fs::read_dir(path)?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|file| file.extension().is_some_and(|ext| ext == MAXMIND_EXT))
+        .collect();
+
+    match files.as_slice() {
+        [file] => Ok(file.clone()),
+        [] => Err(format!("No .mmdb file found in {}", path.display()).into()),
+        _ => Err(format!(
+            "Several .mmdb files found in {}, select one of them",
+            path.display()
+        )
+        .into()),
+    }
+}
+
+/// How downloaded databases are stored in the database path
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DbLayout {
+    /// One `{variant}_{YYYYMMDD}/{variant}.mmdb` directory per database version
+    #[default]
+    Versioned,
+    /// A single `{variant}.mmdb` file which is replaced on updates, as `geoipupdate` does
+    Flat,
+}
+
+impl DbLayout {
+    /// Reads the layout from the `DB_LAYOUT` environment variable (`versioned` or `flat`)
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        match std::env::var("DB_LAYOUT").as_deref() {
+            Ok("versioned") | Err(_) => Ok(Self::Versioned),
+            Ok("flat") => Ok(Self::Flat),
+            Ok(layout) => Err(format!("Invalid DB_LAYOUT: {layout}").into()),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct MaxmindDB {
     pub db: RwLock<MaxmindDBInner>,
     pub variant: String,
     base_path: String,
+    provider: DbProvider,
+    downloader: Downloader,
+    layout: DbLayout,
+    webhooks: Option<Arc<Webhooks>>,
+    overlay: Option<Arc<Overlay>>,
 }
 
 #[derive(Debug)]
@@ -29,15 +95,60 @@ pub struct MaxmindDBInner {
     pub reader: Reader<Vec<u8>>,
     pub filename: String,
     pub base_path: String,
+    modified: Option<SystemTime>,
+    fallback: bool,
+    /// Keeps other processes from removing the database while it is loaded
+    _usage: Option<UsageLock>,
 }
 
 impl MaxmindDB {
     pub async fn init(variant: &str, base_path: &str) -> Result<Self, Box<dyn Error>> {
-        let db_path = match Self::get_latest_variant(variant, base_path).await? {
+        Self::init_with_provider(
+            variant,
+            base_path,
+            DbProvider::from_env()?,
+            DbLayout::from_env()?,
+        )
+        .await
+    }
+
+    pub async fn init_with_provider(
+        variant: &str,
+        base_path: &str,
+        provider: DbProvider,
+        layout: DbLayout,
+    ) -> Result<Self, Box<dyn Error>> {
+        let downloader = Downloader::from_env(&provider)?;
+        Self::init_with_downloader(variant, base_path, provider, downloader, layout).await
+    }
+
+    async fn init_with_downloader(
+        variant: &str,
+        base_path: &str,
+        provider: DbProvider,
+        downloader: Downloader,
+        layout: DbLayout,
+    ) -> Result<Self, Box<dyn Error>> {
+        // Held until the database is loaded, so that concurrently starting processes sharing the
+        // database path do not download it at the same time
+        let _lock = match provider {
+            DbProvider::Local => None,
+            _ => {
+                let lock = UpdateLock::acquire(base_path, variant).await?;
+                remove_orphaned_downloads(base_path, variant).await?;
+                Some(lock)
+            }
+        };
+
+        let db_path = match Self::find_local_db(variant, base_path, layout).await? {
             Some(db) => db,
+            None if matches!(provider, DbProvider::Local) => {
+                return Err(format!("No {variant} database found in {base_path}").into());
+            }
             None => {
                 println!("No database found! Fetching latest from upstream...");
-                Self::fetch_latest_db(variant, base_path).await?
+                provider.forget_download_state(variant, base_path).await;
+                fetch_latest_db(&provider, &downloader, layout, variant, base_path).await?
             }
         };
 
@@ -47,33 +158,53 @@ impl MaxmindDB {
             db: RwLock::new(inner_db),
             variant: variant.to_string(),
             base_path: base_path.to_string(),
+            provider,
+            downloader,
+            layout,
+            webhooks: None,
+            overlay: None,
         })
     }
 
-    async fn fetch_latest_db(variant: &str, output_path: &str) -> Result<PathBuf, Box<dyn Error>> {
-        let db_download_url = env::var("MAXMIND_DB_DOWNLOAD_URL")
-            .unwrap_or(DEFAULT_DB_URL.to_string())
-            .replace("{VARIANT}", variant);
-
-        let account_id = env::var("MAXMIND_ACCOUNT_ID")
-            .map_err(|_| "MAXMIND_ACCOUNT_ID env var not set".to_string())?;
-        let license_key = env::var("MAXMIND_LICENSE_KEY")
-            .map_err(|_| "MAXMIND_LICENSE_KEY env var not set".to_string())?;
-
-        let downloaded_filename = download_with_basic_auth(
-            &db_download_url,
-            output_path,
-            &account_id,
-            Some(&license_key),
+    /// Like [`Self::init_with_provider`], but starts with the database at `fallback_path` (e.g. a
+    /// bundled GeoLite country database) when no database can be loaded or downloaded. The
+    /// fallback is replaced by the first database downloaded by [`UpdatableDB::update_db`].
+    pub async fn init_with_fallback(
+        variant: &str,
+        base_path: &str,
+        provider: DbProvider,
+        layout: DbLayout,
+        fallback_path: &Path,
+    ) -> Result<Self, Box<dyn Error>> {
+        let downloader = Downloader::from_env(&provider)?;
+        let reason = match Self::init_with_downloader(
+            variant,
+            base_path,
+            provider.clone(),
+            downloader.clone(),
+            layout,
         )
-        .await?;
+        .await
+        {
+            Ok(db) => return Ok(db),
+            Err(reason) => reason.to_string(),
+        };
 
-        extract_db(output_path, &downloaded_filename).await?;
+        println!("Failed to load database, starting with fallback database: {reason}");
 
-        let db_dir_name = downloaded_filename.trim_end_matches(".tar.gz");
-        let db_full_path = PathBuf::from(output_path).join(db_dir_name);
+        let mut inner_db = MaxmindDBInner::load_file(fallback_path)?;
+        inner_db.fallback = true;
 
-        Ok(db_full_path)
+        Ok(Self {
+            db: RwLock::new(inner_db),
+            variant: variant.to_string(),
+            base_path: base_path.to_string(),
+            provider,
+            downloader,
+            layout,
+            webhooks: None,
+            overlay: None,
+        })
     }
 
     pub async fn get_latest_variant(
@@ -97,24 +228,290 @@ impl MaxmindDB {
 
         Ok(db_versions.iter().max().cloned())
     }
+
+    /// Sends an event to `webhooks` whenever an update swaps the database or fails
+    pub fn with_webhooks(mut self, webhooks: Option<Webhooks>) -> Self {
+        self.webhooks = webhooks.map(Arc::new);
+        self
+    }
+
+    /// Applies the overrides of `overlay` to all lookups
+    pub fn with_overlay(mut self, overlay: Option<Arc<Overlay>>) -> Self {
+        self.overlay = overlay;
+        self
+    }
+
+    pub fn overlay(&self) -> Option<&Arc<Overlay>> {
+        self.overlay.as_ref()
+    }
+
+    /// Path the databases are stored in
+    pub fn base_path(&self) -> &str {
+        &self.base_path
+    }
+
+    pub fn layout(&self) -> DbLayout {
+        self.layout
+    }
+
+    /// Finds the directory of the database to load from `db_path`. Either the latest versioned
+    /// directory of the variant or `db_path` itself when it contains a `{variant}.mmdb` file,
+    /// preferring the one matching `layout`.
+    pub async fn find_local_db(
+        variant: &str,
+        db_path: &str,
+        layout: DbLayout,
+    ) -> Result<Option<PathBuf>, Box<dyn Error>> {
+        let flat_db = Path::new(db_path).join(format!("{variant}.{MAXMIND_EXT}"));
+        let flat_db_dir = match tokio::fs::try_exists(&flat_db).await? {
+            true => Some(PathBuf::from(db_path)),
+            false => None,
+        };
+
+        match layout {
+            DbLayout::Flat if flat_db_dir.is_some() => Ok(flat_db_dir),
+            _ => Ok(Self::get_latest_variant(variant, db_path)
+                .await?
+                .or(flat_db_dir)),
+        }
+    }
+
+    /// Loads the latest database found on disk if it differs from the loaded one, either by path
+    /// or modification time. Returns whether the database was reloaded.
+    pub async fn reload_from_disk(&self) -> Result<bool, Box<dyn Error>> {
+        let db_path = Self::find_local_db(&self.variant, &self.base_path, self.layout)
+            .await?
+            .ok_or_else(|| format!("No {} database found in {}", self.variant, self.base_path))?;
+
+        {
+            let current_db = self.db.read().await;
+            if Path::new(&current_db.base_path) == db_path
+                && current_db.modified
+                    == MaxmindDBInner::modified(
+                        &db_path.join(format!("{}.{MAXMIND_EXT}", self.variant)),
+                    )
+            {
+                return Ok(false);
+            }
+        }
+
+        let new_db = MaxmindDBInner::load(&db_path, &self.variant)?;
+        self.db.update_inner_db(new_db).await;
+
+        println!("Database reloaded from {}", db_path.to_str().unwrap());
+
+        Ok(true)
+    }
+
+    /// Database directories older than the loaded one, newest first. Empty when the loaded
+    /// database is not in a versioned directory of the database path.
+    pub async fn stale_dbs(&self) -> Vec<PathBuf> {
+        let current_db_path = PathBuf::from(self.db.db_base_path().await);
+
+        // Databases in the flat layout live directly in the base path, which must not be removed.
+        // Neither must anything be removed based on a fallback database outside the base path.
+        if current_db_path.parent() != Some(Path::new(&self.base_path)) {
+            return Vec::new();
+        }
+
+        let Ok(mut entries) = tokio::fs::read_dir(&self.base_path).await else {
+            return Vec::new();
+        };
+
+        let mut stale_dbs = Vec::new();
+
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let stale_db_path = entry.path();
+
+            if entry
+                .file_type()
+                .await
+                .is_ok_and(|file_type| file_type.is_dir())
+                && entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with(&format!("{}_", self.variant))
+                && stale_db_path < current_db_path
+            {
+                stale_dbs.push(stale_db_path);
+            }
+        }
+
+        stale_dbs.sort_by(|a, b| b.cmp(a));
+        stale_dbs
+    }
+
+    /// Removes the database directories older than the loaded one, unless another process
+    /// sharing the database path still uses them. Those are removed by a later update.
+    async fn remove_stale_dbs(&self) {
+        for stale_db_path in self.stale_dbs().await {
+            let db_file = stale_db_path.join(format!("{}.{MAXMIND_EXT}", self.variant));
+            match UsageLock::is_in_use(&db_file) {
+                Ok(false) => {}
+                Ok(true) => {
+                    println!(
+                        "Keeping stale database at {} in use by another process",
+                        stale_db_path.display()
+                    );
+                    continue;
+                }
+                Err(reason) => {
+                    println!("Failed to check stale database {reason:?}");
+                    continue;
+                }
+            }
+
+            println!("Removing stale database at {}", stale_db_path.display());
+
+            if let Err(reason) = tokio::fs::remove_dir_all(&stale_db_path).await {
+                println!("Failed to remove stale database {reason:?}");
+            }
+        }
+    }
+
+    /// Moves the database directory `db_dir` which failed to load out of the way of its download.
+    /// `corrupt` databases are removed, others are renamed to a hidden temporary directory, which
+    /// is removed on a later start. Databases in use are left in place and fail the update.
+    async fn set_aside_unloadable_db(&self, db_dir: &Path, corrupt: bool) -> io::Result<()> {
+        let db_file = db_dir.join(format!("{}.{MAXMIND_EXT}", self.variant));
+        if UsageLock::is_in_use(&db_file)? {
+            return Err(io::Error::other(format!(
+                "Unloadable database at {} is in use",
+                db_dir.display()
+            )));
+        }
+
+        if corrupt {
+            println!("Removing unloadable database at {}", db_dir.display());
+            return tokio::fs::remove_dir_all(db_dir).await;
+        }
+
+        let name = db_dir.file_name().unwrap_or_default().to_string_lossy();
+        let aside_path = temp_path(&self.base_path, &format!("{name}.unloadable"));
+        println!(
+            "Moving unloadable database at {} to {}",
+            db_dir.display(),
+            aside_path.display()
+        );
+        let _ = tokio::fs::remove_dir_all(&aside_path).await;
+        tokio::fs::rename(db_dir, aside_path).await
+    }
 }
 
 impl UpdatableDB for MaxmindDB {
     async fn update_db(&self, db_min_age_secs: u64) -> Result<(), Box<dyn Error>> {
-        if self.db.build_epoch().await + db_min_age_secs > current_time_unix() {
+        let Some(webhooks) = &self.webhooks else {
+            return self.fetch_update(db_min_age_secs).await;
+        };
+
+        let started = Instant::now();
+        let (old_build_epoch, old_directory) = self.version().await;
+        let result = self
+            .fetch_update(db_min_age_secs)
+            .await
+            .map_err(|error| error.to_string());
+        let (new_build_epoch, directory) = self.version().await;
+
+        let swapped = (old_build_epoch, &old_directory) != (new_build_epoch, &directory);
+        if result.is_err() || swapped {
+            let event = UpdateEvent {
+                event: match result {
+                    Ok(()) => UpdateEvent::UPDATED,
+                    Err(_) => UpdateEvent::UPDATE_FAILED,
+                },
+                edition: self.variant.clone(),
+                success: result.is_ok(),
+                old_build_epoch,
+                new_build_epoch,
+                directory,
+                duration_ms: started.elapsed().as_millis() as u64,
+                reason: result.clone().err(),
+            };
+
+            // Delivered in the background, so that slow receivers do not hold up updates
+            let webhooks = Arc::clone(webhooks);
+            tokio::spawn(async move { webhooks.send(&event).await });
+        }
+
+        result.map_err(Into::into)
+    }
+}
+
+impl MaxmindDB {
+    async fn version(&self) -> (u64, String) {
+        let db = self.db.read().await;
+        (db.build_epoch(), db.base_path.clone())
+    }
+
+    async fn fetch_update(&self, db_min_age_secs: u64) -> Result<(), Box<dyn Error>> {
+        // Local databases are managed externally, only pick up their changes
+        if matches!(self.provider, DbProvider::Local) {
+            return self.reload_from_disk().await.map(|_| ());
+        }
+
+        let _lock = UpdateLock::acquire(&self.base_path, &self.variant).await?;
+
+        // Another process sharing the database path may have updated it in the meantime. A local
+        // database which fails to load is replaced by downloading it again.
+        let mut reload_failed = false;
+        let local_db = Self::find_local_db(&self.variant, &self.base_path, self.layout).await?;
+        if let Some(local_db) = local_db {
+            // The error is not `Send`, so it must not be held across the await below
+            let reloaded = self.reload_from_disk().await.map_err(|error| {
+                let corrupt = matches!(
+                    error.downcast_ref::<MaxMindDbError>(),
+                    Some(MaxMindDbError::InvalidDatabase { .. } | MaxMindDbError::Decoding { .. })
+                );
+                (error.to_string(), corrupt)
+            });
+            match reloaded {
+                Ok(true) => {
+                    self.remove_stale_dbs().await;
+                    return Ok(());
+                }
+                Ok(false) => {}
+                Err((reason, corrupt)) => {
+                    println!("Failed to reload local database, downloading it again: {reason}");
+                    // Providers skip versions whose directory exists. Flat databases are replaced
+                    // when they cannot be read.
+                    if local_db.parent() == Some(Path::new(&self.base_path)) {
+                        self.set_aside_unloadable_db(&local_db, corrupt).await?;
+                    }
+                    reload_failed = true;
+                }
+            }
+        }
+
+        // The fallback database is replaced as soon as possible, regardless of its age
+        if reload_failed || self.db.read().await.is_fallback() {
+            self.provider
+                .forget_download_state(&self.variant, &self.base_path)
+                .await;
+        } else if self.db.build_epoch().await + db_min_age_secs > current_time_unix() {
             println!("Database is too new to update");
             return Ok(());
         }
 
-        let latest_db_path = match Self::fetch_latest_db(&self.variant, &self.base_path).await {
-            Ok(path) => path,
+        let latest_db_path = match fetch_latest_db(
+            &self.provider,
+            &self.downloader,
+            self.layout,
+            &self.variant,
+            &self.base_path,
+        )
+        .await
+        {
+            Ok(path) => Some(path),
             Err(error) => match error.downcast_ref::<AlreadyDownloaded>() {
-                Some(AlreadyDownloaded) => return Ok(()),
+                Some(AlreadyDownloaded) => None,
                 None => return Err(error),
             },
         };
 
-        let stale_db_path = self.db.db_base_path().await;
+        let Some(latest_db_path) = latest_db_path else {
+            self.remove_stale_dbs().await;
+            return Ok(());
+        };
 
         let new_db = MaxmindDBInner::load(&latest_db_path, &self.variant)?;
         self.db.update_inner_db(new_db).await;
@@ -124,16 +521,48 @@ impl UpdatableDB for MaxmindDB {
             latest_db_path.to_str().unwrap()
         );
 
-        print!("Removing stale database at {stale_db_path}");
-
-        if let Err(reason) = tokio::fs::remove_dir_all(stale_db_path).await {
-            print!("Failed to remove stale database {reason:?}");
-        };
+        self.remove_stale_dbs().await;
 
         Ok(())
     }
 }
 
+/// Fetches the latest database from the provider and stores it in `layout`
+async fn fetch_latest_db(
+    provider: &DbProvider,
+    downloader: &Downloader,
+    layout: DbLayout,
+    variant: &str,
+    base_path: &str,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let db_dir = provider
+        .fetch_latest_db(downloader, variant, base_path)
+        .await?;
+
+    if layout == DbLayout::Versioned {
+        return Ok(db_dir);
+    }
+
+    let filename = format!("{variant}.{MAXMIND_EXT}");
+    let flat_db = Path::new(base_path).join(&filename);
+
+    // Without version directories, an older or the same database can only be told apart by its
+    // build epoch
+    if let Ok(current_db) = Reader::open_readfile(&flat_db) {
+        let new_db = Reader::open_readfile(db_dir.join(&filename))?;
+
+        if new_db.metadata.build_epoch <= current_db.metadata.build_epoch {
+            tokio::fs::remove_dir_all(&db_dir).await?;
+            return Err(AlreadyDownloaded.into());
+        }
+    }
+
+    tokio::fs::rename(db_dir.join(&filename), &flat_db).await?;
+    tokio::fs::remove_dir_all(&db_dir).await?;
+
+    Ok(PathBuf::from(base_path))
+}
+
 impl<'de> MaxmindDBInner {
     fn load<P: AsRef<Path>, S: AsRef<str>>(
         base_path: P,
@@ -144,19 +573,40 @@ impl<'de> MaxmindDBInner {
         path.push(variant.as_ref());
         path.set_extension(MAXMIND_EXT);
 
+        Self::load_file(&path)
+    }
+
+    fn load_file(path: &Path) -> Result<Self, MaxMindDbError> {
         let filename = path.file_name().unwrap().to_str().unwrap().to_string();
         let full_path = path.to_str().unwrap().to_string();
+        let base_path = path.parent().unwrap_or(Path::new(""));
 
         println!("Loading database from {full_path}");
+        let modified = Self::modified(path);
         let reader = Reader::open_readfile(&full_path)?;
+        let usage = UsageLock::acquire(path)
+            .inspect_err(|reason| println!("Failed to lock database file {reason:?}"))
+            .ok();
 
         Ok(Self {
             reader,
             filename,
-            base_path: base_path.as_ref().to_str().unwrap().to_string(),
+            modified,
+            fallback: false,
+            _usage: usage,
+            base_path: base_path.to_str().unwrap().to_string(),
         })
     }
 
+    fn modified(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    /// Whether this is the fallback database, served until a database is downloaded
+    pub fn is_fallback(&self) -> bool {
+        self.fallback
+    }
+
     pub async fn lookup<T>(&'de self, ip_addresses: Vec<IpAddr>) -> HashMap<IpAddr, Option<T>>
     where
         T: Deserialize<'de>,
@@ -176,6 +626,29 @@ impl<'de> MaxmindDBInner {
             .collect()
     }
 
+    /// Looks up the IP Addresses decoding the records as `lookup_type`. Returns `None` when the
+    /// lookup type is not supported.
+    pub async fn lookup_by_type(
+        &'de self,
+        lookup_type: &str,
+        ip_addresses: Vec<IpAddr>,
+    ) -> Option<LookupResult<'de>> {
+        let results = match lookup_type {
+            "anonymous_ip" => LookupResult::AnonymousIp(self.lookup(ip_addresses).await),
+            "asn" => LookupResult::Asn(self.lookup(ip_addresses).await),
+            "city" => LookupResult::City(self.lookup(ip_addresses).await),
+            "connection_type" => LookupResult::ConnectionType(self.lookup(ip_addresses).await),
+            "country" => LookupResult::Country(self.lookup(ip_addresses).await),
+            "density_income" => LookupResult::DensityIncome(self.lookup(ip_addresses).await),
+            "enterprise" => LookupResult::Enterprise(self.lookup(ip_addresses).await),
+            "isp" => LookupResult::Isp(self.lookup(ip_addresses).await),
+            "raw" => LookupResult::Raw(self.lookup(ip_addresses).await),
+            _ => return None,
+        };
+
+        Some(results)
+    }
+
     pub fn build_epoch(&self) -> u64 {
         self.reader.metadata.build_epoch
     }
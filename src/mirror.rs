@@ -0,0 +1,113 @@
+use crate::maxmind_db::MaxmindDB;
+
+use actix_web::http::header::{AUTHORIZATION, HeaderMap};
+use actix_web::web::Bytes;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::DateTime;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::error::Error;
+use std::path::Path;
+use tokio::sync::Mutex;
+
+/// Serves the loaded database as a MaxMind style `.tar.gz` archive to other Atlas instances, so
+/// that only the mirror downloads from upstream.
+pub struct MirrorConfig {
+    token: String,
+    archive: Mutex<Option<MirrorArchive>>,
+}
+
+/// Archive of the loaded database
+#[derive(Clone)]
+pub struct MirrorArchive {
+    /// `{variant}_{YYYYMMDD}.tar.gz`, the name of the versioned directory the archive contains
+    pub filename: String,
+    pub build_epoch: u64,
+    pub data: Bytes,
+}
+
+impl MirrorConfig {
+    pub fn new(token: String) -> Self {
+        Self {
+            token,
+            archive: Mutex::new(None),
+        }
+    }
+
+    /// Whether the request carries the mirror token, either as the password of basic auth (like
+    /// the MaxMind download API) or as a bearer token.
+    pub fn is_authorized(&self, headers: &HeaderMap) -> bool {
+        let Some(authorization) = headers.get(AUTHORIZATION).and_then(|h| h.to_str().ok()) else {
+            return false;
+        };
+
+        let token = if let Some(credentials) = authorization.strip_prefix("Basic ") {
+            BASE64
+                .decode(credentials.trim())
+                .ok()
+                .and_then(|credentials| String::from_utf8(credentials).ok())
+                .and_then(|credentials| Some(credentials.split_once(':')?.1.to_string()))
+        } else {
+            authorization
+                .strip_prefix("Bearer ")
+                .map(|token| token.trim().to_string())
+        };
+
+        token.is_some_and(|token| constant_time_eq(token.as_bytes(), self.token.as_bytes()))
+    }
+
+    /// Returns the archive of the loaded database. Archives are built once per loaded database.
+    pub async fn archive(&self, db: &MaxmindDB) -> Result<MirrorArchive, Box<dyn Error>> {
+        let (db_path, build_epoch) = {
+            let db = db.db.read().await;
+            (
+                Path::new(&db.base_path).join(&db.filename),
+                db.build_epoch(),
+            )
+        };
+
+        let mut archive = self.archive.lock().await;
+
+        if let Some(archive) = archive.as_ref()
+            && archive.build_epoch == build_epoch
+        {
+            return Ok(archive.clone());
+        }
+
+        let build_date = DateTime::from_timestamp(build_epoch as i64, 0)
+            .ok_or("Invalid database build epoch")?;
+        let dir_name = format!("{}_{}", db.variant, build_date.format("%Y%m%d"));
+        let data = tokio::task::spawn_blocking({
+            let dir_name = dir_name.clone();
+            move || build_archive(&db_path, &dir_name)
+        })
+        .await?
+        .map_err(|error| error.to_string())?;
+
+        let new_archive = MirrorArchive {
+            filename: format!("{dir_name}.tar.gz"),
+            build_epoch,
+            data: Bytes::from(data),
+        };
+        *archive = Some(new_archive.clone());
+
+        Ok(new_archive)
+    }
+}
+
+/// Packs the database at `db_path` as `{dir_name}/{filename}` into a gzip compressed tarball
+fn build_archive(db_path: &Path, dir_name: &str) -> std::io::Result<Vec<u8>> {
+    let filename = db_path
+        .file_name()
+        .ok_or(std::io::ErrorKind::InvalidInput)?;
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+
+    builder.append_path_with_name(db_path, Path::new(dir_name).join(filename))?;
+
+    builder.into_inner()?.finish()
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
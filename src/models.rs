@@ -1,5 +1,6 @@
 use serde::Serialize;
-use std::collections::HashMap;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
 use std::net::IpAddr;
 use utoipa::ToSchema;
 
@@ -7,7 +8,7 @@ use maxminddb::geoip2::{
     AnonymousIp, Asn, City, ConnectionType, Country, DensityIncome, Enterprise, Isp,
 };
 
-type LookupHashMap<T> = HashMap<IpAddr, Option<T>>;
+pub type LookupHashMap<T> = HashMap<IpAddr, Option<T>>;
 
 pub enum LookupResult<'a> {
     AnonymousIp(LookupHashMap<AnonymousIp>),
@@ -18,6 +19,9 @@ pub enum LookupResult<'a> {
     DensityIncome(LookupHashMap<DensityIncome>),
     Enterprise(LookupHashMap<Enterprise<'a>>),
     Isp(LookupHashMap<Isp<'a>>),
+    /// Records of any database decoded as is
+    Raw(LookupHashMap<Value>),
+    Flat(LookupHashMap<FlatLookupResult>),
 }
 
 impl Serialize for LookupResult<'_> {
@@ -26,14 +30,95 @@ impl Serialize for LookupResult<'_> {
         S: serde::Serializer,
     {
         match self {
-            Self::AnonymousIp(anonymous_ip) => anonymous_ip.serialize(serializer),
-            Self::Asn(asn) => asn.serialize(serializer),
-            Self::City(city) => city.serialize(serializer),
-            Self::ConnectionType(connection_type) => connection_type.serialize(serializer),
-            Self::Country(country) => country.serialize(serializer),
-            Self::DensityIncome(density_income) => density_income.serialize(serializer),
-            Self::Enterprise(enterprise) => enterprise.serialize(serializer),
-            Self::Isp(isp) => isp.serialize(serializer),
+            Self::AnonymousIp(anonymous_ip) => serialize_results(anonymous_ip, serializer),
+            Self::Asn(asn) => serialize_results(asn, serializer),
+            Self::City(city) => serialize_results(city, serializer),
+            Self::ConnectionType(connection_type) => serialize_results(connection_type, serializer),
+            Self::Country(country) => serialize_results(country, serializer),
+            Self::DensityIncome(density_income) => serialize_results(density_income, serializer),
+            Self::Enterprise(enterprise) => serialize_results(enterprise, serializer),
+            Self::Isp(isp) => serialize_results(isp, serializer),
+            Self::Raw(raw) => serialize_results(raw, serializer),
+            Self::Flat(flat) => serialize_results(flat, serializer),
+        }
+    }
+}
+
+// IP addresses are serialized as strings so that map keys stay the same in non human-readable
+// formats (e.g. MessagePack) as they are in JSON.
+fn serialize_results<S, T>(results: &LookupHashMap<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    T: Serialize,
+{
+    serializer.collect_map(results.iter().map(|(ip, result)| (ip.to_string(), result)))
+}
+
+impl LookupResult<'_> {
+    /// Converts the lookup results into the simplified [`FlatLookupResult`] representation.
+    pub fn flatten(&self) -> LookupHashMap<FlatLookupResult> {
+        fn map<T>(results: &LookupHashMap<T>) -> LookupHashMap<FlatLookupResult>
+        where
+            for<'r> &'r T: Into<FlatLookupResult>,
+        {
+            results
+                .iter()
+                .map(|(ip, result)| (*ip, result.as_ref().map(Into::into)))
+                .collect()
+        }
+
+        match self {
+            Self::AnonymousIp(results) => map(results),
+            Self::Asn(results) => map(results),
+            Self::City(results) => map(results),
+            Self::ConnectionType(results) => map(results),
+            Self::Country(results) => map(results),
+            Self::DensityIncome(results) => map(results),
+            Self::Enterprise(results) => map(results),
+            Self::Isp(results) => map(results),
+            Self::Raw(results) => map(results),
+            Self::Flat(results) => results.clone(),
+        }
+    }
+
+    /// The looked up IP Addresses
+    pub fn ips(&self) -> Vec<IpAddr> {
+        match self {
+            Self::AnonymousIp(results) => results.keys().copied().collect(),
+            Self::Asn(results) => results.keys().copied().collect(),
+            Self::City(results) => results.keys().copied().collect(),
+            Self::ConnectionType(results) => results.keys().copied().collect(),
+            Self::Country(results) => results.keys().copied().collect(),
+            Self::DensityIncome(results) => results.keys().copied().collect(),
+            Self::Enterprise(results) => results.keys().copied().collect(),
+            Self::Isp(results) => results.keys().copied().collect(),
+            Self::Raw(results) => results.keys().copied().collect(),
+            Self::Flat(results) => results.keys().copied().collect(),
+        }
+    }
+
+    /// Converts the lookup results into records with the structure they are encoded with.
+    pub fn into_values(self) -> Result<LookupHashMap<Value>, serde_json::Error> {
+        fn map<T: Serialize>(
+            results: LookupHashMap<T>,
+        ) -> Result<LookupHashMap<Value>, serde_json::Error> {
+            results
+                .into_iter()
+                .map(|(ip, result)| Ok((ip, result.map(serde_json::to_value).transpose()?)))
+                .collect()
+        }
+
+        match self {
+            Self::AnonymousIp(results) => map(results),
+            Self::Asn(results) => map(results),
+            Self::City(results) => map(results),
+            Self::ConnectionType(results) => map(results),
+            Self::Country(results) => map(results),
+            Self::DensityIncome(results) => map(results),
+            Self::Enterprise(results) => map(results),
+            Self::Isp(results) => map(results),
+            Self::Raw(results) => Ok(results),
+            Self::Flat(results) => map(results),
         }
     }
 }
@@ -42,6 +127,284 @@ impl Serialize for LookupResult<'_> {
 pub struct LookupResponseModel<'a> {
     pub results: LookupResult<'a>,
     pub database_build_epoch: u64,
+    /// Overlay network of every IP Address whose result was overridden by the overlay
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    #[schema(example = json!({"10.1.2.3": "10.1.0.0/16"}))]
+    pub overrides: BTreeMap<String, String>,
 }
 
 pub struct HealthCheckModel;
+
+/// A stable, flat representation of a lookup result which is the same for all database types.
+///
+/// Fields which are not available in the queried database are always present with a `null` value.
+#[derive(Serialize, ToSchema, Clone, Debug, Default, PartialEq)]
+pub struct FlatLookupResult {
+    #[schema(example = "NA")]
+    pub continent_code: Option<String>,
+    #[schema(example = "US")]
+    pub country_code: Option<String>,
+    #[schema(example = "United States")]
+    pub country_name: Option<String>,
+    #[schema(example = "CA")]
+    pub region_code: Option<String>,
+    #[schema(example = "California")]
+    pub region: Option<String>,
+    #[schema(example = "San Diego")]
+    pub city: Option<String>,
+    #[schema(example = "92101")]
+    pub postal: Option<String>,
+    #[schema(example = 32.7203)]
+    pub lat: Option<f64>,
+    #[schema(example = -117.1552)]
+    pub lon: Option<f64>,
+    #[schema(example = 100)]
+    pub accuracy_radius: Option<u16>,
+    #[schema(example = "America/Los_Angeles")]
+    pub timezone: Option<String>,
+    #[schema(example = 13335)]
+    pub asn: Option<u32>,
+    #[schema(example = "Cloudflare")]
+    pub org: Option<String>,
+    #[schema(example = "Cloudflare")]
+    pub isp: Option<String>,
+    #[schema(example = "Corporate")]
+    pub connection_type: Option<String>,
+    pub is_anonymous: Option<bool>,
+    pub is_anonymous_vpn: Option<bool>,
+    pub is_hosting_provider: Option<bool>,
+    pub is_public_proxy: Option<bool>,
+    pub is_residential_proxy: Option<bool>,
+    pub is_tor_exit_node: Option<bool>,
+    pub is_anycast: Option<bool>,
+}
+
+impl FlatLookupResult {
+    /// Overrides the fields which are set in `overrides`, keeping all others
+    pub fn merge(self, overrides: Self) -> Self {
+        Self {
+            continent_code: overrides.continent_code.or(self.continent_code),
+            country_code: overrides.country_code.or(self.country_code),
+            country_name: overrides.country_name.or(self.country_name),
+            region_code: overrides.region_code.or(self.region_code),
+            region: overrides.region.or(self.region),
+            city: overrides.city.or(self.city),
+            postal: overrides.postal.or(self.postal),
+            lat: overrides.lat.or(self.lat),
+            lon: overrides.lon.or(self.lon),
+            accuracy_radius: overrides.accuracy_radius.or(self.accuracy_radius),
+            timezone: overrides.timezone.or(self.timezone),
+            asn: overrides.asn.or(self.asn),
+            org: overrides.org.or(self.org),
+            isp: overrides.isp.or(self.isp),
+            connection_type: overrides.connection_type.or(self.connection_type),
+            is_anonymous: overrides.is_anonymous.or(self.is_anonymous),
+            is_anonymous_vpn: overrides.is_anonymous_vpn.or(self.is_anonymous_vpn),
+            is_hosting_provider: overrides.is_hosting_provider.or(self.is_hosting_provider),
+            is_public_proxy: overrides.is_public_proxy.or(self.is_public_proxy),
+            is_residential_proxy: overrides.is_residential_proxy.or(self.is_residential_proxy),
+            is_tor_exit_node: overrides.is_tor_exit_node.or(self.is_tor_exit_node),
+            is_anycast: overrides.is_anycast.or(self.is_anycast),
+        }
+    }
+}
+
+fn owned(value: Option<&str>) -> Option<String> {
+    value.map(str::to_string)
+}
+
+impl From<&AnonymousIp> for FlatLookupResult {
+    fn from(anonymous_ip: &AnonymousIp) -> Self {
+        Self {
+            is_anonymous: anonymous_ip.is_anonymous,
+            is_anonymous_vpn: anonymous_ip.is_anonymous_vpn,
+            is_hosting_provider: anonymous_ip.is_hosting_provider,
+            is_public_proxy: anonymous_ip.is_public_proxy,
+            is_residential_proxy: anonymous_ip.is_residential_proxy,
+            is_tor_exit_node: anonymous_ip.is_tor_exit_node,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&Asn<'_>> for FlatLookupResult {
+    fn from(asn: &Asn) -> Self {
+        Self {
+            asn: asn.autonomous_system_number,
+            org: owned(asn.autonomous_system_organization),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&City<'_>> for FlatLookupResult {
+    fn from(city: &City) -> Self {
+        let subdivision = city.subdivisions.first();
+
+        Self {
+            continent_code: owned(city.continent.code),
+            country_code: owned(city.country.iso_code),
+            country_name: owned(city.country.names.english),
+            region_code: owned(subdivision.and_then(|s| s.iso_code)),
+            region: owned(subdivision.and_then(|s| s.names.english)),
+            city: owned(city.city.names.english),
+            postal: owned(city.postal.code),
+            lat: city.location.latitude,
+            lon: city.location.longitude,
+            accuracy_radius: city.location.accuracy_radius,
+            timezone: owned(city.location.time_zone),
+            is_anycast: city.traits.is_anycast,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&ConnectionType<'_>> for FlatLookupResult {
+    fn from(connection_type: &ConnectionType) -> Self {
+        Self {
+            connection_type: owned(connection_type.connection_type),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&Country<'_>> for FlatLookupResult {
+    fn from(country: &Country) -> Self {
+        Self {
+            continent_code: owned(country.continent.code),
+            country_code: owned(country.country.iso_code),
+            country_name: owned(country.country.names.english),
+            is_anycast: country.traits.is_anycast,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&DensityIncome> for FlatLookupResult {
+    fn from(_density_income: &DensityIncome) -> Self {
+        Self::default()
+    }
+}
+
+impl From<&Enterprise<'_>> for FlatLookupResult {
+    fn from(enterprise: &Enterprise) -> Self {
+        let subdivision = enterprise.subdivisions.first();
+        let traits = &enterprise.traits;
+
+        Self {
+            continent_code: owned(enterprise.continent.code),
+            country_code: owned(enterprise.country.iso_code),
+            country_name: owned(enterprise.country.names.english),
+            region_code: owned(subdivision.and_then(|s| s.iso_code)),
+            region: owned(subdivision.and_then(|s| s.names.english)),
+            city: owned(enterprise.city.names.english),
+            postal: owned(enterprise.postal.code),
+            lat: enterprise.location.latitude,
+            lon: enterprise.location.longitude,
+            accuracy_radius: enterprise.location.accuracy_radius,
+            timezone: owned(enterprise.location.time_zone),
+            asn: traits.autonomous_system_number,
+            org: owned(
+                traits
+                    .organization
+                    .or(traits.autonomous_system_organization),
+            ),
+            isp: owned(traits.isp),
+            connection_type: owned(traits.connection_type),
+            is_anonymous: traits.is_anonymous,
+            is_anonymous_vpn: traits.is_anonymous_vpn,
+            is_hosting_provider: traits.is_hosting_provider,
+            is_public_proxy: traits.is_public_proxy,
+            is_residential_proxy: traits.is_residential_proxy,
+            is_tor_exit_node: traits.is_tor_exit_node,
+            is_anycast: traits.is_anycast,
+        }
+    }
+}
+
+impl From<&Isp<'_>> for FlatLookupResult {
+    fn from(isp: &Isp) -> Self {
+        Self {
+            asn: isp.autonomous_system_number,
+            org: owned(isp.organization.or(isp.autonomous_system_organization)),
+            isp: owned(isp.isp),
+            ..Default::default()
+        }
+    }
+}
+
+/// Best effort mapping of records from any database. Supports GeoIP2 compatible records (e.g.
+/// DB-IP) as well as flat records (e.g. IPinfo).
+impl From<&Value> for FlatLookupResult {
+    fn from(record: &Value) -> Self {
+        let string = |pointers: &[&str]| {
+            pointers
+                .iter()
+                .find_map(|pointer| record.pointer(pointer)?.as_str())
+                .map(str::to_string)
+        };
+        // Some vendors use the same key for codes and names, only two letter values are codes
+        let code = |pointers: &[&str]| {
+            pointers
+                .iter()
+                .filter_map(|pointer| record.pointer(pointer)?.as_str())
+                .find(|value| value.len() == 2)
+                .map(str::to_string)
+        };
+        let float = |pointer: &str| record.pointer(pointer)?.as_f64();
+        let flag = |pointer: &str| record.pointer(pointer)?.as_bool();
+
+        let asn = [
+            "/autonomous_system_number",
+            "/traits/autonomous_system_number",
+            "/asn",
+        ]
+        .iter()
+        .find_map(|pointer| match record.pointer(pointer)? {
+            Value::Number(number) => number.as_u64()?.try_into().ok(),
+            Value::String(asn) => asn.trim_start_matches("AS").parse().ok(),
+            _ => None,
+        });
+
+        Self {
+            continent_code: code(&["/continent/code", "/continent_code", "/continent"]),
+            country_code: code(&["/country/iso_code", "/country_code", "/country"]),
+            country_name: string(&["/country/names/en", "/country_name"]).or_else(|| {
+                record
+                    .pointer("/country")?
+                    .as_str()
+                    .filter(|country| country.len() > 2)
+                    .map(str::to_string)
+            }),
+            // ISO 3166-2 subdivision codes have one to three characters
+            region_code: string(&["/subdivisions/0/iso_code"]),
+            region: string(&["/subdivisions/0/names/en", "/region"]),
+            city: string(&["/city/names/en", "/city"]),
+            postal: string(&["/postal/code", "/postal_code", "/postal"]),
+            lat: float("/location/latitude").or_else(|| float("/latitude")),
+            lon: float("/location/longitude").or_else(|| float("/longitude")),
+            accuracy_radius: record
+                .pointer("/location/accuracy_radius")
+                .and_then(Value::as_u64)
+                .and_then(|radius| radius.try_into().ok()),
+            timezone: string(&["/location/time_zone", "/timezone"]),
+            asn,
+            org: string(&[
+                "/organization",
+                "/traits/organization",
+                "/autonomous_system_organization",
+                "/as_name",
+                "/org",
+            ]),
+            isp: string(&["/isp", "/traits/isp"]),
+            connection_type: string(&["/connection_type", "/traits/connection_type"]),
+            is_anonymous: flag("/is_anonymous"),
+            is_anonymous_vpn: flag("/is_anonymous_vpn"),
+            is_hosting_provider: flag("/is_hosting_provider"),
+            is_public_proxy: flag("/is_public_proxy"),
+            is_residential_proxy: flag("/is_residential_proxy"),
+            is_tor_exit_node: flag("/is_tor_exit_node"),
+            is_anycast: flag("/traits/is_anycast").or_else(|| flag("/is_anycast")),
+        }
+    }
+}
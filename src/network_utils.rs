@@ -1,4 +1,7 @@
-use std::net::IpAddr;
+use actix_web::HttpRequest;
+use ipnet::IpNet;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
 
 pub trait SpecialIPCheck {
     fn is_special_ip(&self) -> bool;
@@ -18,3 +21,166 @@ impl SpecialIPCheck for IpAddr {
         }
     }
 }
+
+/// Headers which reverse proxies use to pass the original client IP Address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProxyHeader {
+    XForwardedFor,
+    Forwarded,
+    CfConnectingIp,
+}
+
+impl FromStr for ProxyHeader {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "x-forwarded-for" => Ok(Self::XForwardedFor),
+            "forwarded" => Ok(Self::Forwarded),
+            "cf-connecting-ip" => Ok(Self::CfConnectingIp),
+            header => Err(format!("Unsupported proxy header {header:?}")),
+        }
+    }
+}
+
+impl ProxyHeader {
+    fn name(self) -> &'static str {
+        match self {
+            Self::XForwardedFor => "x-forwarded-for",
+            Self::Forwarded => "forwarded",
+            Self::CfConnectingIp => "cf-connecting-ip",
+        }
+    }
+
+    /// Returns the chain of IP Addresses in the header in the order they were added, the first one
+    /// being the furthest from us. An unparsable entry is returned as `None`.
+    fn ip_chain(self, req: &HttpRequest) -> Option<Vec<Option<IpAddr>>> {
+        let values: Vec<&str> = req
+            .headers()
+            .get_all(self.name())
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+
+        if values.is_empty() {
+            return None;
+        }
+
+        let chain = values
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(|element| match self {
+                Self::XForwardedFor | Self::CfConnectingIp => parse_forwarded_ip(element),
+                Self::Forwarded => element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, value)| parse_forwarded_ip(value)),
+            })
+            .collect();
+
+        Some(chain)
+    }
+}
+
+/// Parses an IP Address which might be quoted, wrapped in brackets or followed by a port number.
+fn parse_forwarded_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+
+    value
+        .parse()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            value
+                .strip_prefix('[')
+                .and_then(|v| v.strip_suffix(']'))
+                .and_then(|v| v.parse().ok())
+        })
+        .or_else(|| {
+            value
+                .rsplit_once(':')
+                .and_then(|(ip, _port)| ip.parse::<Ipv4Addr>().ok())
+                .map(IpAddr::V4)
+        })
+}
+
+/// Configuration for deriving the IP Address of the client making the request.
+///
+/// Proxy headers are only taken into account when the request is coming from one of the trusted
+/// proxies. Otherwise the peer address of the connection is used.
+#[derive(Clone, Debug, Default)]
+pub struct ClientIpConfig {
+    pub trusted_proxies: Vec<IpNet>,
+    pub proxy_headers: Vec<ProxyHeader>,
+}
+
+impl ClientIpConfig {
+    /// Parses a comma separated list of trusted proxy networks (or single IP Addresses) and a
+    /// comma separated list of proxy headers to look into, in order of priority.
+    pub fn parse(trusted_proxies: &str, proxy_headers: &str) -> Result<Self, String> {
+        let trusted_proxies = trusted_proxies
+            .split(',')
+            .map(str::trim)
+            .filter(|network| !network.is_empty())
+            .map(|network| {
+                network
+                    .parse::<IpNet>()
+                    .or_else(|_| network.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| format!("Invalid trusted proxy network {network:?}"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let proxy_headers = proxy_headers
+            .split(',')
+            .filter(|header| !header.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            trusted_proxies,
+            proxy_headers,
+        })
+    }
+
+    fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies
+            .iter()
+            .any(|network| network.contains(ip))
+    }
+
+    /// Returns the IP Address of the client or `None` if it cannot be determined.
+    ///
+    /// Proxy header chains are walked from the closest hop backwards and the first address which is
+    /// not a trusted proxy is considered the client. The walk stops at an unparsable hop (e.g.
+    /// `for=unknown`), so the last hop which could be parsed is considered the client then.
+    pub fn client_ip(&self, req: &HttpRequest) -> Option<IpAddr> {
+        let peer_ip = req.peer_addr()?.ip().to_canonical();
+
+        if !self.is_trusted(&peer_ip) {
+            return Some(peer_ip);
+        }
+
+        let Some(chain) = self
+            .proxy_headers
+            .iter()
+            .find_map(|header| header.ip_chain(req))
+        else {
+            return Some(peer_ip);
+        };
+
+        let mut client_ip = peer_ip;
+
+        for ip in chain.into_iter().rev() {
+            let Some(ip) = ip else {
+                break;
+            };
+            client_ip = ip.to_canonical();
+
+            if !self.is_trusted(&client_ip) {
+                break;
+            }
+        }
+
+        Some(client_ip)
+    }
+}
@@ -0,0 +1,394 @@
+use crate::models::{FlatLookupResult, LookupHashMap, LookupResult};
+
+use ipnet::IpNet;
+use serde::Deserialize;
+use serde_json::{Map, Number, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::error::Error;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// Fields of MaxMind records which hold numbers. Fields starting with `is_` hold booleans, all
+/// others strings.
+const NUMBER_FIELDS: &[&str] = &[
+    "accuracy_radius",
+    "autonomous_system_number",
+    "average_income",
+    "confidence",
+    "geoname_id",
+    "latitude",
+    "longitude",
+    "metro_code",
+    "population_density",
+    "static_ip_score",
+    "user_count",
+];
+
+/// How the fields of an overlay entry are applied to the database record
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverlayMode {
+    /// Overrides the fields in the record, keeping all others
+    #[default]
+    Merge,
+    /// Replaces the record with the fields
+    Replace,
+}
+
+impl OverlayMode {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "" | "merge" => Some(Self::Merge),
+            "replace" => Some(Self::Replace),
+            _ => None,
+        }
+    }
+}
+
+/// Field overrides of a network
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverlayEntry {
+    pub network: IpNet,
+    pub mode: OverlayMode,
+    /// Values by their path in the record, e.g. `["country", "iso_code"]`
+    pub fields: Vec<(Vec<String>, Value)>,
+}
+
+impl OverlayEntry {
+    /// Applies the overrides to the record of an IP Address
+    pub fn apply(&self, record: Option<Value>) -> Value {
+        let mut record = match (self.mode, record) {
+            (OverlayMode::Merge, Some(record)) => record,
+            _ => Value::Object(Map::new()),
+        };
+
+        for (path, value) in &self.fields {
+            set(&mut record, path, value.clone());
+        }
+
+        record
+    }
+
+    /// Applies the overrides to the flat result of an IP Address. Only the flat fields named by
+    /// the overridden paths change, e.g. `country_code` for `country.iso_code`.
+    pub fn apply_flat(&self, result: Option<FlatLookupResult>) -> FlatLookupResult {
+        let overrides = FlatLookupResult::from(&self.apply(None));
+
+        match (self.mode, result) {
+            (OverlayMode::Merge, Some(result)) => result.merge(overrides),
+            _ => overrides,
+        }
+    }
+}
+
+/// Sets the value at `path`, creating objects (or arrays for numeric segments) on the way
+fn set(target: &mut Value, path: &[String], value: Value) {
+    let Some((segment, rest)) = path.split_first() else {
+        *target = value;
+        return;
+    };
+
+    if let Ok(index) = segment.parse::<usize>()
+        && (target.is_null() || target.is_array())
+    {
+        if target.is_null() {
+            *target = Value::Array(Vec::new());
+        }
+        let Value::Array(items) = target else {
+            unreachable!();
+        };
+        if items.len() <= index {
+            items.resize(index + 1, Value::Null);
+        }
+        return set(&mut items[index], rest, value);
+    }
+
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let Value::Object(object) = target else {
+        unreachable!();
+    };
+
+    set(
+        object.entry(segment.clone()).or_insert(Value::Null),
+        rest,
+        value,
+    );
+}
+
+/// Entry of a YAML overlay file
+#[derive(Deserialize)]
+struct YamlEntry {
+    network: String,
+    #[serde(default)]
+    mode: OverlayMode,
+    #[serde(default)]
+    fields: Map<String, Value>,
+}
+
+/// Parses the entries of an overlay file. Files ending in `.yaml` or `.yml` are read as YAML, all
+/// others as CSV.
+pub fn parse_entries(path: &Path, contents: &str) -> Result<Vec<OverlayEntry>, Box<dyn Error>> {
+    let mut entries = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yaml" | "yml") => parse_yaml(contents)?,
+        _ => parse_csv(contents)?,
+    };
+
+    // The most specific network of an IP Address is the last one containing it
+    entries.sort_by_key(|entry| entry.network.prefix_len());
+
+    Ok(entries)
+}
+
+fn parse_network(network: &str) -> Result<IpNet, Box<dyn Error>> {
+    let network = network.trim();
+
+    network
+        .parse::<IpNet>()
+        .or_else(|_| network.parse::<IpAddr>().map(IpNet::from))
+        .map(|network| network.trunc())
+        .map_err(|_| format!("Invalid network {network:?}").into())
+}
+
+fn parse_path(key: &str) -> Vec<String> {
+    key.split('.').map(String::from).collect()
+}
+
+fn parse_yaml(contents: &str) -> Result<Vec<OverlayEntry>, Box<dyn Error>> {
+    let entries: Vec<YamlEntry> = serde_yaml::from_str(contents)?;
+
+    entries
+        .into_iter()
+        .map(|entry| {
+            let mut fields = Vec::new();
+            for (key, value) in entry.fields {
+                leaves(parse_path(&key), value, &mut fields);
+            }
+
+            Ok(OverlayEntry {
+                network: parse_network(&entry.network)?,
+                mode: entry.mode,
+                fields,
+            })
+        })
+        .collect()
+}
+
+/// Collects the paths of all values of nested objects
+fn leaves(path: Vec<String>, value: Value, fields: &mut Vec<(Vec<String>, Value)>) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                let mut path = path.clone();
+                path.extend(parse_path(&key));
+                leaves(path, value, fields);
+            }
+        }
+        value => fields.push((path, value)),
+    }
+}
+
+/// Parses a CSV cell as the type of the field at `path`. Cells of string fields are kept as they
+/// are, e.g. postal codes like `92101`.
+fn parse_cell(path: &[String], cell: &str) -> Result<Value, Box<dyn Error>> {
+    let field = path.last().map(String::as_str).unwrap_or_default();
+
+    let value = if field.starts_with("is_") {
+        cell.parse().ok().map(Value::Bool)
+    } else if NUMBER_FIELDS.contains(&field) {
+        cell.parse::<Number>().ok().map(Value::Number)
+    } else {
+        return Ok(Value::String(cell.to_string()));
+    };
+
+    value.ok_or_else(|| format!("Invalid value {cell:?} of {}", path.join(".")).into())
+}
+
+fn parse_csv(contents: &str) -> Result<Vec<OverlayEntry>, Box<dyn Error>> {
+    let mut reader = csv::Reader::from_reader(contents.as_bytes());
+    let headers = reader.headers()?.clone();
+
+    let network_column = headers
+        .iter()
+        .position(|header| header == "network")
+        .ok_or("Missing network column")?;
+    let mode_column = headers.iter().position(|header| header == "mode");
+
+    reader
+        .records()
+        .map(|record| {
+            let record = record?;
+            let mode = match mode_column.and_then(|column| record.get(column)) {
+                Some(mode) => OverlayMode::from_name(mode.trim())
+                    .ok_or_else(|| format!("Invalid mode {mode:?}"))?,
+                None => OverlayMode::Merge,
+            };
+
+            // Empty cells don't override anything
+            let fields = headers
+                .iter()
+                .zip(record.iter())
+                .enumerate()
+                .filter(|(column, (_, value))| {
+                    Some(*column) != mode_column && *column != network_column && !value.is_empty()
+                })
+                .map(|(_, (header, value))| {
+                    let path = parse_path(header);
+                    let value = parse_cell(&path, value)?;
+                    Ok((path, value))
+                })
+                .collect::<Result<_, Box<dyn Error>>>()?;
+
+            Ok(OverlayEntry {
+                network: parse_network(&record[network_column])?,
+                mode,
+                fields,
+            })
+        })
+        .collect()
+}
+
+/// In-house annotations of IP ranges loaded from a CSV or YAML file, which take precedence over
+/// the database. The file can be reloaded without restarting the server.
+#[derive(Debug)]
+pub struct Overlay {
+    path: PathBuf,
+    current: RwLock<LoadedOverlay>,
+}
+
+#[derive(Debug)]
+struct LoadedOverlay {
+    entries: Vec<OverlayEntry>,
+    modified: SystemTime,
+}
+
+impl Overlay {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let current = Self::load_entries(path)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            current: RwLock::new(current),
+        })
+    }
+
+    /// Loads the overlay at `OVERLAY_PATH`. Returns `None` when it is not set.
+    pub fn from_env() -> Result<Option<Arc<Self>>, Box<dyn Error>> {
+        match env::var("OVERLAY_PATH") {
+            Ok(path) => Ok(Some(Arc::new(Self::load(path.as_ref())?))),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn load_entries(path: &Path) -> Result<LoadedOverlay, Box<dyn Error>> {
+        let modified = std::fs::metadata(path)?.modified()?;
+        let contents = std::fs::read_to_string(path)
+            .map_err(|error| format!("Failed to read overlay {}: {error}", path.display()))?;
+        let entries = parse_entries(path, &contents)
+            .map_err(|error| format!("Invalid overlay {}: {error}", path.display()))?;
+
+        Ok(LoadedOverlay { entries, modified })
+    }
+
+    /// Reloads the file when it was modified since the last load. Returns whether it was
+    /// reloaded. On failure the current entries are kept.
+    pub fn reload_if_changed(&self) -> Result<bool, Box<dyn Error>> {
+        let modified = std::fs::metadata(&self.path)?.modified()?;
+
+        if self.current.read().unwrap().modified == modified {
+            return Ok(false);
+        }
+
+        let overlay = Self::load_entries(&self.path)?;
+        *self.current.write().unwrap() = overlay;
+
+        Ok(true)
+    }
+
+    /// Periodically checks the file for changes and reloads it.
+    pub async fn watch(self: Arc<Self>, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+
+            match self.reload_if_changed() {
+                Ok(true) => println!("Reloaded overlay {:?}", self.path),
+                Ok(false) => {}
+                Err(reason) => println!("Failed to reload overlay {reason:?}"),
+            }
+        }
+    }
+
+    /// Number of loaded entries
+    pub fn len(&self) -> usize {
+        self.current.read().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The most specific entry containing `ip`
+    pub fn find(&self, ip: IpAddr) -> Option<OverlayEntry> {
+        self.current
+            .read()
+            .unwrap()
+            .entries
+            .iter()
+            .rev()
+            .find(|entry| entry.network.contains(&ip))
+            .cloned()
+    }
+
+    /// Applies the overrides to the lookup results. Returns the results and the overlay network of
+    /// every overridden IP Address.
+    ///
+    /// Flat results are overridden per IP Address, so flatten results before applying the overlay.
+    /// Other results with overrides are converted to records of [`LookupResult::Raw`], which are
+    /// encoded the same way.
+    pub fn apply<'a>(
+        &self,
+        results: LookupResult<'a>,
+    ) -> Result<(LookupResult<'a>, BTreeMap<IpAddr, IpNet>), serde_json::Error> {
+        let matches: HashMap<IpAddr, OverlayEntry> = results
+            .ips()
+            .into_iter()
+            .filter_map(|ip| Some((ip, self.find(ip)?)))
+            .collect();
+
+        if matches.is_empty() {
+            return Ok((results, BTreeMap::new()));
+        }
+
+        let results = match results {
+            LookupResult::Flat(results) => LookupResult::Flat(
+                results
+                    .into_iter()
+                    .map(|(ip, result)| match matches.get(&ip) {
+                        Some(entry) => (ip, Some(entry.apply_flat(result))),
+                        None => (ip, result),
+                    })
+                    .collect(),
+            ),
+            results => LookupResult::Raw(
+                results
+                    .into_values()?
+                    .into_iter()
+                    .map(|(ip, record)| match matches.get(&ip) {
+                        Some(entry) => (ip, Some(entry.apply(record))),
+                        None => (ip, record),
+                    })
+                    .collect::<LookupHashMap<Value>>(),
+            ),
+        };
+        let overrides = matches
+            .into_iter()
+            .map(|(ip, entry)| (ip, entry.network))
+            .collect();
+
+        Ok((results, overrides))
+    }
+}
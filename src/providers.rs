@@ -0,0 +1,384 @@
+use crate::download_utils::{
+    AlreadyDownloaded, DownloadState, HttpClientConfig, PARTIAL_EXT, RetryPolicy, download_file,
+    download_with_basic_auth, extract_db, gunzip, temp_path,
+};
+use crate::geoip_conf::GeoIpConf;
+use crate::s3::S3Config;
+use chrono::{DateTime, Datelike, Utc};
+use maxminddb::Reader;
+use std::{
+    env,
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+const DEFAULT_MAXMIND_DB_URL: &str =
+    "https://download.maxmind.com/geoip/databases/{VARIANT}/download?suffix=tar.gz";
+const DEFAULT_DBIP_DB_URL: &str =
+    "https://download.db-ip.com/free/dbip-{VARIANT}-lite-{YEAR}-{MONTH}.mmdb.gz";
+const DEFAULT_IPINFO_DB_URL: &str = "https://ipinfo.io/data/free/{VARIANT}.mmdb?token={TOKEN}";
+
+/// Upstream source the databases are downloaded from.
+#[derive(Debug, Clone)]
+pub enum DbProvider {
+    /// MaxMind's download API. Serves `.tar.gz` archives and authenticates with the account ID
+    /// and license key.
+    MaxMind {
+        download_url: String,
+        account_id: Option<String>,
+        license_key: Option<String>,
+        /// Proxy of the `geoipupdate` configuration, used when `DOWNLOAD_PROXY` is not set
+        proxy: Option<String>,
+    },
+    /// Another Atlas instance serving its database on `/mirror/{variant}`, authenticated with
+    /// the instance's mirror token. Serves archives the same way MaxMind does.
+    Mirror { url: String, token: Option<String> },
+    /// Any URL serving a plain `.mmdb` or gzip compressed `.mmdb.gz` file (e.g. DB-IP Lite or
+    /// IPinfo).
+    ///
+    /// `{VARIANT}`, `{YEAR}`, `{MONTH}` and `{TOKEN}` literals in the URL are replaced by the
+    /// database variant, current UTC year and month and the token.
+    Url { url: String, token: Option<String> },
+    /// S3 compatible bucket the databases are uploaded to. The most recently uploaded database of
+    /// the variant is downloaded.
+    S3(S3Config),
+    /// Databases placed in the database path by an external process. Nothing is downloaded,
+    /// changed files are reloaded instead.
+    Local,
+}
+
+/// HTTP client and retry policy of the downloads, built once from the environment
+#[derive(Debug, Clone)]
+pub struct Downloader {
+    pub client: reqwest::Client,
+    pub retry: RetryPolicy,
+}
+
+impl Downloader {
+    /// Reads the `DOWNLOAD_*` settings. The `geoipupdate` proxy of a MaxMind `provider` is used
+    /// when no proxy is configured.
+    pub fn from_env(provider: &DbProvider) -> Result<Self, Box<dyn Error>> {
+        let mut client_config = HttpClientConfig::from_env()?;
+        if let DbProvider::MaxMind {
+            proxy: Some(proxy), ..
+        } = provider
+        {
+            client_config.proxy.get_or_insert_with(|| proxy.clone());
+        }
+
+        Ok(Self {
+            client: client_config.build()?,
+            retry: RetryPolicy::from_env()?,
+        })
+    }
+}
+
+impl DbProvider {
+    /// Builds the provider from the `DB_PROVIDER` environment variable (`maxmind`, `dbip`,
+    /// `ipinfo`, `url`, `mirror`, `s3` or `local`). Defaults to `maxmind`.
+    ///
+    /// MaxMind settings which are not set in the environment are read from the `geoipupdate`
+    /// configuration at `GEOIP_CONF_PATH`, if any.
+    ///
+    /// Credentials are only checked when a database is downloaded, so that an already downloaded
+    /// database can be served without them.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let provider = env::var("DB_PROVIDER").unwrap_or("maxmind".to_string());
+        let download_url = env::var("DB_DOWNLOAD_URL").ok();
+        let token = env::var("DB_DOWNLOAD_TOKEN").ok();
+
+        let provider = match provider.as_str() {
+            "maxmind" => {
+                let conf = GeoIpConf::from_env()?.unwrap_or_default();
+
+                Self::MaxMind {
+                    download_url: env::var("MAXMIND_DB_DOWNLOAD_URL")
+                        .ok()
+                        .or(download_url)
+                        .or(conf.download_url())
+                        .unwrap_or(DEFAULT_MAXMIND_DB_URL.to_string()),
+                    account_id: env::var("MAXMIND_ACCOUNT_ID")
+                        .ok()
+                        .or(conf.account_id.clone()),
+                    license_key: env::var("MAXMIND_LICENSE_KEY")
+                        .ok()
+                        .or(conf.license_key.clone()),
+                    proxy: conf.proxy_url(),
+                }
+            }
+            "dbip" => Self::Url {
+                url: download_url.unwrap_or(DEFAULT_DBIP_DB_URL.to_string()),
+                token,
+            },
+            "ipinfo" => Self::Url {
+                url: download_url.unwrap_or(DEFAULT_IPINFO_DB_URL.to_string()),
+                token,
+            },
+            "url" => Self::Url {
+                url: download_url.ok_or("DB_DOWNLOAD_URL env var not set")?,
+                token,
+            },
+            "mirror" => Self::Mirror {
+                url: download_url.ok_or("DB_DOWNLOAD_URL env var not set")?,
+                token,
+            },
+            "s3" => Self::S3(S3Config::from_env()?),
+            "local" => Self::Local,
+            provider => return Err(format!("Invalid DB_PROVIDER: {provider}").into()),
+        };
+
+        Ok(provider)
+    }
+
+    fn state_path(variant: &str, output_path: &str) -> PathBuf {
+        PathBuf::from(output_path).join(format!("{variant}.download.json"))
+    }
+
+    /// Forgets what was downloaded last, so that the next download is not skipped when the
+    /// database did not change upstream (e.g. because the downloaded database was removed).
+    pub async fn forget_download_state(&self, variant: &str, output_path: &str) {
+        let _ = tokio::fs::remove_file(Self::state_path(variant, output_path)).await;
+    }
+
+    /// Downloads the latest database of `variant` into a versioned directory in `output_path`
+    /// and returns the path of the directory.
+    ///
+    /// Fails with [`AlreadyDownloaded`] when the latest database already exists.
+    pub async fn fetch_latest_db(
+        &self,
+        downloader: &Downloader,
+        variant: &str,
+        output_path: &str,
+    ) -> Result<PathBuf, Box<dyn Error>> {
+        let state_path = Self::state_path(variant, output_path);
+        let state = DownloadState::load(&state_path).await;
+        let Downloader { client, retry } = downloader;
+
+        match self {
+            Self::MaxMind {
+                download_url,
+                account_id,
+                license_key,
+                ..
+            } => {
+                let account_id = account_id
+                    .as_deref()
+                    .ok_or("MAXMIND_ACCOUNT_ID env var not set")?;
+                let license_key = license_key
+                    .as_deref()
+                    .ok_or("MAXMIND_LICENSE_KEY env var not set")?;
+                fetch_archive(
+                    client,
+                    &download_url.replace("{VARIANT}", variant),
+                    output_path,
+                    (account_id, license_key),
+                    &state,
+                    &state_path,
+                    retry,
+                )
+                .await
+            }
+            Self::Mirror { url, token } => {
+                let token = token
+                    .as_deref()
+                    .ok_or("DB_DOWNLOAD_TOKEN env var not set")?;
+                let url = format!("{}/mirror/{variant}", url.trim_end_matches('/'));
+
+                fetch_archive(
+                    client,
+                    &url,
+                    output_path,
+                    ("atlas", token),
+                    &state,
+                    &state_path,
+                    retry,
+                )
+                .await
+            }
+            Self::Url { url, token } => {
+                let url = render_url(url, variant, token.as_deref(), Utc::now())?;
+                let download_path =
+                    PathBuf::from(output_path).join(format!("{variant}.{PARTIAL_EXT}"));
+                let new_state = download_file(client, &url, &download_path, &state, retry).await?;
+                let mut db = tokio::fs::read(&download_path).await?;
+                tokio::fs::remove_file(&download_path).await?;
+
+                if url_path(&url).ends_with(".gz") {
+                    db = gunzip(&db)?;
+                }
+
+                let db_dir = match save_versioned_db(db, variant, output_path).await {
+                    Ok(db_dir) => Some(db_dir),
+                    Err(error) if error.downcast_ref::<AlreadyDownloaded>().is_some() => None,
+                    Err(error) => return Err(error),
+                };
+
+                // An already existing database is still recorded, so it is not downloaded again
+                save_state(&new_state, &state_path).await;
+
+                db_dir.ok_or_else(|| AlreadyDownloaded.into())
+            }
+            Self::S3(s3) => {
+                let object = s3
+                    .latest_object(client, variant, retry)
+                    .await
+                    .map_err(|error| error as Box<dyn Error>)?
+                    .ok_or_else(|| {
+                        format!("No {variant} database found in S3 bucket {}", s3.bucket)
+                    })?;
+
+                // The listing tells whether the object changed, without downloading it
+                let new_state = DownloadState {
+                    etag: Some(object.etag.clone()),
+                    last_modified: Some(object.last_modified.clone()),
+                };
+                if state.matches(&new_state) {
+                    return Err(AlreadyDownloaded.into());
+                }
+
+                let filename = object.filename();
+                let archive_dir =
+                    PathBuf::from(output_path).join(filename.trim_end_matches(".tar.gz"));
+                if filename.ends_with(".tar.gz") && tokio::fs::try_exists(&archive_dir).await? {
+                    save_state(&new_state, &state_path).await;
+                    return Err(AlreadyDownloaded.into());
+                }
+
+                println!("Downloading {} from S3 bucket {}", object.key, s3.bucket);
+
+                let download_path =
+                    PathBuf::from(output_path).join(format!("{variant}.{PARTIAL_EXT}"));
+                let url = s3
+                    .object_url(&object.key)
+                    .map_err(|error| error as Box<dyn Error>)?;
+                download_file(
+                    client,
+                    &url,
+                    &download_path,
+                    &DownloadState::default(),
+                    retry,
+                )
+                .await?;
+
+                if let Err(error) = s3.verify(client, &object, &download_path, retry).await {
+                    tokio::fs::remove_file(&download_path).await?;
+                    return Err(error);
+                }
+
+                let db_dir = if filename.ends_with(".tar.gz") {
+                    tokio::fs::rename(&download_path, PathBuf::from(output_path).join(filename))
+                        .await?;
+                    extract_db(output_path, filename).await?;
+                    Some(archive_dir)
+                } else {
+                    let mut db = tokio::fs::read(&download_path).await?;
+                    tokio::fs::remove_file(&download_path).await?;
+
+                    if filename.ends_with(".gz") {
+                        db = gunzip(&db)?;
+                    }
+
+                    match save_versioned_db(db, variant, output_path).await {
+                        Ok(db_dir) => Some(db_dir),
+                        Err(error) if error.downcast_ref::<AlreadyDownloaded>().is_some() => None,
+                        Err(error) => return Err(error),
+                    }
+                };
+
+                save_state(&new_state, &state_path).await;
+
+                db_dir.ok_or_else(|| AlreadyDownloaded.into())
+            }
+            Self::Local => Err("The local database provider does not download databases".into()),
+        }
+    }
+}
+
+/// Downloads a MaxMind style `.tar.gz` archive and extracts it in `output_path`
+async fn fetch_archive(
+    client: &reqwest::Client,
+    url: &str,
+    output_path: &str,
+    (username, password): (&str, &str),
+    state: &DownloadState,
+    state_path: &Path,
+    retry: &RetryPolicy,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let (downloaded_filename, new_state) = download_with_basic_auth(
+        client,
+        url,
+        output_path,
+        username,
+        Some(password),
+        state,
+        retry,
+    )
+    .await?;
+
+    extract_db(output_path, &downloaded_filename).await?;
+    save_state(&new_state, state_path).await;
+
+    let db_dir_name = downloaded_filename.trim_end_matches(".tar.gz");
+
+    Ok(PathBuf::from(output_path).join(db_dir_name))
+}
+
+async fn save_state(state: &DownloadState, path: &Path) {
+    if let Some(last_modified) = &state.last_modified {
+        println!("Upstream database last modified at {last_modified}");
+    }
+
+    if let Err(reason) = state.save(path).await {
+        println!("Failed to save download state {reason:?}");
+    }
+}
+
+fn render_url(
+    url: &str,
+    variant: &str,
+    token: Option<&str>,
+    now: DateTime<Utc>,
+) -> Result<String, Box<dyn Error>> {
+    if url.contains("{TOKEN}") && token.is_none() {
+        return Err("DB_DOWNLOAD_TOKEN env var not set".into());
+    }
+
+    Ok(url
+        .replace("{VARIANT}", variant)
+        .replace("{YEAR}", &now.year().to_string())
+        .replace("{MONTH}", &format!("{:02}", now.month()))
+        .replace("{TOKEN}", token.unwrap_or_default()))
+}
+
+fn url_path(url: &str) -> &str {
+    url.split(['?', '#']).next().unwrap_or(url)
+}
+
+/// Validates the database and saves it as `{variant}_{YYYYMMDD}/{variant}.mmdb` using its build
+/// date, the same layout MaxMind archives have.
+async fn save_versioned_db(
+    db: Vec<u8>,
+    variant: &str,
+    output_path: &str,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let build_epoch = Reader::from_source(db.as_slice())?.metadata.build_epoch;
+    let build_date =
+        DateTime::from_timestamp(build_epoch as i64, 0).ok_or("Invalid database build epoch")?;
+
+    let db_dir =
+        PathBuf::from(output_path).join(format!("{variant}_{}", build_date.format("%Y%m%d")));
+
+    if tokio::fs::try_exists(&db_dir).await? {
+        return Err(AlreadyDownloaded.into());
+    }
+
+    println!("Saving database in {}", db_dir.to_str().unwrap());
+
+    // Written to a temporary directory first, so that the database is complete once it appears
+    let temp_dir = temp_path(output_path, &db_dir.file_name().unwrap().to_string_lossy());
+    tokio::fs::create_dir_all(&temp_dir).await?;
+    tokio::fs::write(temp_dir.join(format!("{variant}.mmdb")), db).await?;
+    tokio::fs::rename(&temp_dir, &db_dir).await?;
+
+    Ok(db_dir)
+}
@@ -0,0 +1,291 @@
+use crate::auth::AuthenticatedKey;
+use crate::network_utils::ClientIpConfig;
+use crate::services::too_many_requests;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{HttpMessage, web};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Default maximum number of tracked clients. Beyond it, idle clients are pruned and then the
+/// least recently seen ones are evicted.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// A token bucket which holds up to `capacity` tokens and is refilled at a constant rate.
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket which allows bursts of up to `capacity` tokens and refills completely
+    /// in `period`.
+    pub fn new(capacity: u32, period: Duration) -> Self {
+        let capacity = f64::from(capacity);
+
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_per_sec: capacity / period.as_secs_f64(),
+            last_refill: Instant::now(),
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity as u32
+    }
+
+    fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.capacity
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// Takes `cost` tokens from the bucket. When there are not enough tokens, nothing is taken and
+    /// the time to wait until there will be is returned instead. A cost larger than the capacity
+    /// of the bucket can never be taken.
+    pub fn try_take(&mut self, cost: u32) -> Result<(), LimitExceeded> {
+        if cost > self.capacity() {
+            return Err(LimitExceeded::TooManyIps {
+                limit: self.capacity(),
+            });
+        }
+
+        self.refill();
+
+        let cost = f64::from(cost);
+
+        if self.tokens >= cost {
+            self.tokens -= cost;
+            Ok(())
+        } else {
+            Err(LimitExceeded::RetryAfter(Duration::from_secs_f64(
+                (cost - self.tokens) / self.refill_per_sec,
+            )))
+        }
+    }
+}
+
+/// Why a lookup was refused by the [`RateLimiter`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    /// The client is over its limit until the time has passed
+    RetryAfter(Duration),
+    /// The lookup has more IP Addresses than the limit allows per minute, so it never passes
+    TooManyIps { limit: u32 },
+}
+
+/// Client whose lookups are limited
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ClientKey {
+    ApiKey(String),
+    Ip(IpAddr),
+    /// Clients whose IP Address cannot be determined, which share their limits
+    Unknown,
+}
+
+impl ClientKey {
+    /// IPv6 clients are identified by their /64 network, as they usually get a whole one.
+    pub fn from_ip(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(_) => Self::Ip(ip),
+            IpAddr::V6(ip) => Self::Ip(IpAddr::V6(Ipv6Addr::from_bits(
+                ip.to_bits() & !u128::from(u64::MAX),
+            ))),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ClientBuckets {
+    single: Option<TokenBucket>,
+    batch: Option<TokenBucket>,
+    last_seen: Instant,
+}
+
+impl ClientBuckets {
+    fn is_idle(&mut self) -> bool {
+        self.single.as_mut().is_none_or(TokenBucket::is_full)
+            && self.batch.as_mut().is_none_or(TokenBucket::is_full)
+    }
+}
+
+/// Limits lookups per client, identified by their API key or otherwise their IP Address.
+///
+/// Single IP lookups and batch lookups have separate limits. Batch lookups take one token per
+/// looked up IP Address from the batch bucket.
+#[derive(Debug)]
+pub struct RateLimiter {
+    single_per_minute: Option<u32>,
+    batch_ips_per_minute: Option<u32>,
+    max_tracked_clients: usize,
+    clients: Mutex<HashMap<ClientKey, ClientBuckets>>,
+}
+
+impl RateLimiter {
+    /// Returns `None` when neither of the limits is set.
+    pub fn new(single_per_minute: Option<u32>, batch_ips_per_minute: Option<u32>) -> Option<Self> {
+        if single_per_minute.is_none() && batch_ips_per_minute.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            single_per_minute,
+            batch_ips_per_minute,
+            max_tracked_clients: MAX_TRACKED_CLIENTS,
+            clients: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Limits the number of clients whose buckets are kept in memory
+    pub fn with_max_tracked_clients(mut self, max_tracked_clients: usize) -> Self {
+        self.max_tracked_clients = max_tracked_clients.max(1);
+        self
+    }
+
+    /// Number of clients whose buckets are kept in memory
+    pub fn tracked_clients(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    fn new_bucket(per_minute: Option<u32>) -> Option<TokenBucket> {
+        per_minute.map(|limit| TokenBucket::new(limit, Duration::from_secs(60)))
+    }
+
+    /// Takes the tokens of a lookup of `ip_count` IP Addresses by `client`. When the client is
+    /// over its limit, returns the time to wait until it is not.
+    pub fn check(&self, client: ClientKey, ip_count: u32) -> Result<(), LimitExceeded> {
+        let mut clients = self.clients.lock().unwrap();
+
+        if !clients.contains_key(&client) && clients.len() >= self.max_tracked_clients {
+            self.prune(&mut clients);
+        }
+
+        let buckets = clients.entry(client).or_insert_with(|| ClientBuckets {
+            single: Self::new_bucket(self.single_per_minute),
+            batch: Self::new_bucket(self.batch_ips_per_minute),
+            last_seen: Instant::now(),
+        });
+        buckets.last_seen = Instant::now();
+
+        let bucket = if ip_count > 1 {
+            buckets.batch.as_mut()
+        } else {
+            buckets.single.as_mut()
+        };
+
+        match bucket {
+            Some(bucket) => bucket.try_take(ip_count),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes idle clients and, when that is not enough, the least recently seen ones down to
+    /// three quarters of the maximum, so that pruning happens rarely even when all clients are
+    /// active.
+    fn prune(&self, clients: &mut HashMap<ClientKey, ClientBuckets>) {
+        clients.retain(|_, buckets| !buckets.is_idle());
+
+        let target = self.max_tracked_clients * 3 / 4;
+        if clients.len() <= target {
+            return;
+        }
+
+        let evicted = clients.len() - target;
+        let mut last_seen: Vec<Instant> =
+            clients.values().map(|buckets| buckets.last_seen).collect();
+        let cutoff = *last_seen.select_nth_unstable(evicted - 1).1;
+
+        let mut remaining = evicted;
+        clients.retain(|_, buckets| {
+            if remaining > 0 && buckets.last_seen <= cutoff {
+                remaining -= 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+/// Number of IP Addresses looked up by a request to `/geoip/lookup/{lookup_type}/{ip_addresses}`.
+fn lookup_ip_count(path: &str) -> Option<u32> {
+    let ip_addresses = path.strip_prefix("/geoip/lookup/")?.split_once('/')?.1;
+    let count = ip_addresses.split(',').count();
+
+    Some(u32::try_from(count).unwrap_or(u32::MAX))
+}
+
+/// Middleware which enforces the limits of the `RateLimiter` registered as app data on lookups.
+///
+/// Must run after `auth::require_api_key` so authenticated requests are limited by API key.
+pub async fn limit_lookups(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let Some(rate_limiter) = req.app_data::<web::Data<RateLimiter>>().cloned() else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    };
+
+    // The path as it is routed, so that e.g. `%2C` separated IP Addresses are counted
+    let Some(ip_count) = lookup_ip_count(req.match_info().as_str()) else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    };
+
+    let api_key = req
+        .extensions()
+        .get::<AuthenticatedKey>()
+        .map(|key| ClientKey::ApiKey(key.0.clone()));
+
+    let client = api_key.unwrap_or_else(|| {
+        let client_ip = match req.app_data::<web::Data<ClientIpConfig>>() {
+            Some(config) => config.client_ip(req.request()),
+            None => ClientIpConfig::default().client_ip(req.request()),
+        };
+
+        client_ip.map_or(ClientKey::Unknown, ClientKey::from_ip)
+    });
+
+    match rate_limiter.check(client, ip_count) {
+        Ok(()) => next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body),
+        Err(limit_exceeded) => {
+            let resp = match limit_exceeded {
+                LimitExceeded::RetryAfter(retry_after) => too_many_requests(
+                    "Rate limit exceeded".to_string(),
+                    "RATE_LIMITED".to_string(),
+                    Some(retry_after),
+                ),
+                LimitExceeded::TooManyIps { limit } => too_many_requests(
+                    format!("Batch lookups are limited to {limit} IP Addresses per minute"),
+                    "RATE_LIMITED".to_string(),
+                    None,
+                ),
+            };
+
+            Ok(req.into_response(resp).map_into_right_body())
+        }
+    }
+}
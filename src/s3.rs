@@ -0,0 +1,324 @@
+use crate::download_utils::{RetryPolicy, SendError, send_with_retries};
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{env, error::Error, path::Path, time::Duration};
+
+const DEFAULT_REGION: &str = "us-east-1";
+/// Validity of presigned URLs, long enough for slow downloads to be resumed
+const PRESIGNED_URL_EXPIRY: Duration = Duration::from_secs(60 * 60);
+const DB_EXTENSIONS: [&str; 3] = [".tar.gz", ".mmdb.gz", ".mmdb"];
+
+/// S3 compatible bucket (AWS S3, MinIO, Cloudflare R2, ...) the databases are uploaded to.
+///
+/// Objects are addressed path style (`{endpoint}/{bucket}/{key}`), which every S3 compatible
+/// server supports.
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    pub endpoint: String,
+    pub bucket: String,
+    /// Prefix of the database objects, e.g. `geoip/`. Objects are listed by `{prefix}{variant}`.
+    pub prefix: String,
+    pub region: String,
+    /// Anonymous requests are made without credentials
+    pub credentials: Option<S3Credentials>,
+}
+
+#[derive(Debug, Clone)]
+pub struct S3Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+/// Object of a bucket listing
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct S3Object {
+    pub key: String,
+    pub last_modified: String,
+    #[serde(rename = "ETag", default)]
+    pub etag: String,
+    pub size: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListBucketResult {
+    #[serde(default)]
+    contents: Vec<S3Object>,
+    #[serde(default)]
+    is_truncated: bool,
+    next_continuation_token: Option<String>,
+}
+
+impl S3Object {
+    /// Name of the object without the prefix
+    pub fn filename(&self) -> &str {
+        self.key.rsplit('/').next().unwrap_or(&self.key)
+    }
+}
+
+impl S3Config {
+    /// Reads `S3_BUCKET`, `S3_ENDPOINT`, `S3_REGION`, `S3_PREFIX` and the standard
+    /// `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN` variables.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let region = env::var("S3_REGION")
+            .or(env::var("AWS_REGION"))
+            .unwrap_or(DEFAULT_REGION.to_string());
+
+        let credentials = match (
+            env::var("AWS_ACCESS_KEY_ID"),
+            env::var("AWS_SECRET_ACCESS_KEY"),
+        ) {
+            (Ok(access_key_id), Ok(secret_access_key)) => Some(S3Credentials {
+                access_key_id,
+                secret_access_key,
+                session_token: env::var("AWS_SESSION_TOKEN").ok(),
+            }),
+            (Err(_), Err(_)) => None,
+            _ => {
+                return Err(
+                    "Both AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY must be set for S3".into(),
+                );
+            }
+        };
+
+        Ok(Self {
+            endpoint: env::var("S3_ENDPOINT")
+                .unwrap_or(format!("https://s3.{region}.amazonaws.com"))
+                .trim_end_matches('/')
+                .to_string(),
+            bucket: env::var("S3_BUCKET").map_err(|_| "S3_BUCKET env var not set")?,
+            prefix: env::var("S3_PREFIX").unwrap_or_default(),
+            region,
+            credentials,
+        })
+    }
+
+    /// Lists all objects whose key starts with `prefix`
+    pub async fn list_objects(
+        &self,
+        client: &reqwest::Client,
+        prefix: &str,
+        retry: &RetryPolicy,
+    ) -> Result<Vec<S3Object>, SendError> {
+        let mut objects = Vec::new();
+        let mut continuation_token: Option<String> = None;
+
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+            if let Some(token) = &continuation_token {
+                query.push(("continuation-token", token.as_str()));
+            }
+
+            let url = self.url("", &query)?;
+            let response = send_with_retries(&client.get(url), retry, "S3 list").await?;
+
+            if !response.status().is_success() {
+                return Err(
+                    format!("Bad S3 list response status code: {}", response.status()).into(),
+                );
+            }
+
+            let result: ListBucketResult = quick_xml::de::from_str(&response.text().await?)?;
+            objects.extend(result.contents);
+
+            match result.next_continuation_token {
+                Some(token) if result.is_truncated => continuation_token = Some(token),
+                _ => return Ok(objects),
+            }
+        }
+    }
+
+    /// Returns the most recently uploaded database of `variant`. Databases are `{variant}*.tar.gz`
+    /// archives in the MaxMind layout or `{variant}*.mmdb` files, optionally gzip compressed.
+    pub async fn latest_object(
+        &self,
+        client: &reqwest::Client,
+        variant: &str,
+        retry: &RetryPolicy,
+    ) -> Result<Option<S3Object>, SendError> {
+        let objects = self
+            .list_objects(client, &format!("{}{variant}", self.prefix), retry)
+            .await?;
+
+        Ok(objects
+            .into_iter()
+            .filter(|object| {
+                object.filename().starts_with(variant)
+                    && DB_EXTENSIONS.iter().any(|ext| object.key.ends_with(ext))
+            })
+            .max_by(|a, b| (&a.last_modified, &a.key).cmp(&(&b.last_modified, &b.key))))
+    }
+
+    /// Presigned URL of the object, which can be downloaded (and resumed) without further
+    /// authentication
+    pub fn object_url(&self, key: &str) -> Result<String, SendError> {
+        self.url(key, &[])
+    }
+
+    /// Verifies a downloaded object by its size and, when a `{key}.sha256` object exists next to
+    /// it (as MaxMind publishes them), its SHA-256 checksum
+    pub async fn verify(
+        &self,
+        client: &reqwest::Client,
+        object: &S3Object,
+        path: &Path,
+        retry: &RetryPolicy,
+    ) -> Result<(), SendError> {
+        let content = tokio::fs::read(path).await?;
+
+        if content.len() as u64 != object.size {
+            return Err(format!(
+                "Downloaded {} has {} bytes instead of {}",
+                object.key,
+                content.len(),
+                object.size
+            )
+            .into());
+        }
+
+        let checksum_url = self.object_url(&format!("{}.sha256", object.key))?;
+        let response = send_with_retries(&client.get(checksum_url), retry, "checksum").await?;
+
+        if !response.status().is_success() {
+            return Ok(());
+        }
+
+        let expected = response.text().await?;
+        let expected = expected.split_whitespace().next().unwrap_or_default();
+        let actual = hex(&Sha256::digest(&content));
+
+        if !expected.eq_ignore_ascii_case(&actual) {
+            return Err(format!(
+                "Checksum mismatch of {}: {actual} != {expected}",
+                object.key
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    fn url(&self, key: &str, query: &[(&str, &str)]) -> Result<String, SendError> {
+        let path = match key {
+            "" => format!("/{}", self.bucket),
+            key => format!("/{}/{key}", self.bucket),
+        };
+        let query = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", uri_encode(name, true), uri_encode(value, true)))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let url = match query.is_empty() {
+            true => format!("{}{}", self.endpoint, uri_encode(&path, false)),
+            false => format!("{}{}?{query}", self.endpoint, uri_encode(&path, false)),
+        };
+
+        match &self.credentials {
+            Some(credentials) => presign_url(
+                &url,
+                &self.region,
+                credentials,
+                Utc::now(),
+                PRESIGNED_URL_EXPIRY,
+            ),
+            None => Ok(url),
+        }
+    }
+}
+
+/// Signs a `GET` request to `url` with AWS Signature Version 4 in the query string. `url` must
+/// already be URI encoded.
+pub fn presign_url(
+    url: &str,
+    region: &str,
+    credentials: &S3Credentials,
+    now: DateTime<Utc>,
+    expires: Duration,
+) -> Result<String, SendError> {
+    let parsed_url = reqwest::Url::parse(url)?;
+    let host = match parsed_url.port() {
+        Some(port) => format!("{}:{port}", parsed_url.host_str().ok_or("Missing host")?),
+        None => parsed_url.host_str().ok_or("Missing host")?.to_string(),
+    };
+
+    let date = now.format("%Y%m%d").to_string();
+    let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let scope = format!("{date}/{region}/s3/aws4_request");
+
+    let mut query: Vec<(String, String)> = parsed_url
+        .query_pairs()
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    query.extend([
+        ("X-Amz-Algorithm".into(), "AWS4-HMAC-SHA256".into()),
+        (
+            "X-Amz-Credential".into(),
+            format!("{}/{scope}", credentials.access_key_id),
+        ),
+        ("X-Amz-Date".into(), timestamp.clone()),
+        ("X-Amz-Expires".into(), expires.as_secs().to_string()),
+        ("X-Amz-SignedHeaders".into(), "host".into()),
+    ]);
+    if let Some(session_token) = &credentials.session_token {
+        query.push(("X-Amz-Security-Token".into(), session_token.clone()));
+    }
+
+    let mut query: Vec<String> = query
+        .iter()
+        .map(|(name, value)| format!("{}={}", uri_encode(name, true), uri_encode(value, true)))
+        .collect();
+    query.sort();
+    let query = query.join("&");
+
+    let canonical_request = format!(
+        "GET\n{}\n{query}\nhost:{host}\n\nhost\nUNSIGNED-PAYLOAD",
+        parsed_url.path()
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{timestamp}\n{scope}\n{}",
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let signing_key = [region, "s3", "aws4_request"].iter().fold(
+        hmac_sha256(
+            format!("AWS4{}", credentials.secret_access_key).as_bytes(),
+            date.as_bytes(),
+        ),
+        |key, data| hmac_sha256(&key, data.as_bytes()),
+    );
+    let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+    let base_url = url.split('?').next().unwrap_or(url);
+
+    Ok(format!("{base_url}?{query}&X-Amz-Signature={signature}"))
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// URI encodes everything except the unreserved characters, as required by Signature Version 4
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            b'/' if !encode_slash => "/".to_string(),
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
@@ -0,0 +1,115 @@
+use crate::admin::AdminConfig;
+use crate::db_diff::{self, DiffFormat};
+use crate::maxmind_db::{DbLayout, MaxmindDB};
+use crate::services::{bad_request, internal_server_error, not_found, unauthorized};
+
+use actix_web::{HttpRequest, HttpResponse, Responder, get, web};
+use serde::Deserialize;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Deserialize)]
+struct DiffQuery {
+    /// Directory or file in the database path, defaults to the newest older database
+    old: Option<String>,
+    /// Directory or file in the database path, defaults to the loaded database
+    new: Option<String>,
+    format: Option<String>,
+}
+
+/// Compares two databases in the database path, by default the loaded one and the newest older
+/// one, which the next update removes.
+#[get("/admin/diff")]
+pub async fn diff(
+    req: HttpRequest,
+    query: web::Query<DiffQuery>,
+    data: web::Data<MaxmindDB>,
+    admin: web::Data<AdminConfig>,
+) -> impl Responder {
+    if !admin.is_authorized(req.headers()) {
+        return unauthorized(
+            "Missing or invalid admin token".to_string(),
+            "UNAUTHORIZED".to_string(),
+        );
+    }
+
+    let format = match query.format.as_deref().map(DiffFormat::from_name) {
+        None => DiffFormat::Json,
+        Some(Some(format)) => format,
+        Some(None) => {
+            return bad_request(
+                format!(
+                    "Invalid format {:?}",
+                    query.format.as_deref().unwrap_or_default()
+                ),
+                "INVALID_FORMAT".to_string(),
+            );
+        }
+    };
+
+    let new_path = match &query.new {
+        Some(name) => db_path(&data, name),
+        None => Ok(PathBuf::from(data.db.read().await.base_path.clone())),
+    };
+    let old_path = match &query.old {
+        Some(name) => db_path(&data, name),
+        // The flat layout replaces the database in place, so no previous version is kept
+        None if data.layout() == DbLayout::Flat => Err(bad_request(
+            "No previous database is kept in the flat layout, select one with the old parameter"
+                .to_string(),
+            "OLD_DB_REQUIRED".to_string(),
+        )),
+        None => data.stale_dbs().await.into_iter().next().ok_or_else(|| {
+            not_found(
+                "No older database to compare with".to_string(),
+                "DB_NOT_FOUND".to_string(),
+            )
+        }),
+    };
+    let (old_path, new_path) = match (old_path, new_path) {
+        (Ok(old_path), Ok(new_path)) => (old_path, new_path),
+        (Err(resp), _) | (_, Err(resp)) => return resp,
+    };
+
+    let variant = data.variant.clone();
+    let report = web::block(move || {
+        let mut body = Vec::new();
+        db_diff::diff(&old_path, &new_path, Some(&variant))
+            .and_then(|report| report.write(format, &mut body))
+            .map(|_| body)
+            .map_err(|error| error.to_string())
+    })
+    .await
+    .unwrap_or_else(|error| Err(error.to_string()));
+
+    match report {
+        Ok(body) => HttpResponse::Ok()
+            .content_type(format.content_type())
+            .body(body),
+        Err(error) => internal_server_error(
+            format!("Failed to compare databases: {error}"),
+            "DIFF_ERROR".to_string(),
+        ),
+    }
+}
+
+/// Resolves the name of a directory or file in the database path. Other paths are rejected.
+fn db_path(data: &MaxmindDB, name: &str) -> Result<PathBuf, HttpResponse> {
+    let mut components = Path::new(name).components();
+    let path = match (components.next(), components.next()) {
+        (Some(Component::Normal(name)), None) => Path::new(data.base_path()).join(name),
+        _ => {
+            return Err(bad_request(
+                format!("Invalid database name {name:?}"),
+                "INVALID_DB_NAME".to_string(),
+            ));
+        }
+    };
+
+    match path.exists() {
+        true => Ok(path),
+        false => Err(not_found(
+            format!("No database {name:?} found"),
+            "DB_NOT_FOUND".to_string(),
+        )),
+    }
+}
@@ -0,0 +1,161 @@
+use super::{bad_request, internal_server_error};
+use crate::models::LookupResponseModel;
+
+use actix_web::http::header::{self, Header};
+use actix_web::{HttpRequest, HttpResponse};
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::net::IpAddr;
+
+const CSV_CONTENT_TYPE: &str = "text/csv";
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+const CBOR_CONTENT_TYPE: &str = "application/cbor";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResponseFormat {
+    Json,
+    Flat,
+    Csv,
+    Ndjson,
+    MessagePack,
+    Cbor,
+}
+
+impl ResponseFormat {
+    /// Picks the response format from the `format` query parameter, falling back to the `Accept`
+    /// header of the request and finally JSON.
+    pub fn negotiate(format: Option<&str>, req: &HttpRequest) -> Result<Self, HttpResponse> {
+        match format {
+            Some(format) => Self::from_name(format).ok_or_else(|| {
+                bad_request(
+                    format!("Invalid format {format:?}"),
+                    "INVALID_FORMAT".to_string(),
+                )
+            }),
+            None => Ok(Self::from_accept_header(req)),
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "default" | "json" => Some(Self::Json),
+            "flat" => Some(Self::Flat),
+            "csv" => Some(Self::Csv),
+            "ndjson" => Some(Self::Ndjson),
+            "msgpack" => Some(Self::MessagePack),
+            "cbor" => Some(Self::Cbor),
+            _ => None,
+        }
+    }
+
+    fn from_accept_header(req: &HttpRequest) -> Self {
+        let Ok(accept) = header::Accept::parse(req) else {
+            return Self::Json;
+        };
+
+        accept
+            .ranked()
+            .iter()
+            .find_map(|mime| match mime.essence_str() {
+                "application/json" => Some(Self::Json),
+                CSV_CONTENT_TYPE => Some(Self::Csv),
+                NDJSON_CONTENT_TYPE | "application/jsonl" => Some(Self::Ndjson),
+                MSGPACK_CONTENT_TYPE | "application/x-msgpack" | "application/vnd.msgpack" => {
+                    Some(Self::MessagePack)
+                }
+                CBOR_CONTENT_TYPE => Some(Self::Cbor),
+                _ => None,
+            })
+            .unwrap_or(Self::Json)
+    }
+
+    /// Whether the results should be converted to `FlatLookupResult`s before encoding.
+    pub fn is_flat(self) -> bool {
+        matches!(self, Self::Flat | Self::Csv)
+    }
+
+    pub fn respond(self, response: &LookupResponseModel) -> HttpResponse {
+        let body = match self {
+            Self::Json | Self::Flat => return HttpResponse::Ok().json(response),
+            Self::Csv => encode_csv(response),
+            Self::Ndjson => encode_ndjson(response),
+            Self::MessagePack => rmp_serde::to_vec_named(response).map_err(|e| e.to_string()),
+            Self::Cbor => {
+                let mut body = Vec::new();
+                ciborium::into_writer(response, &mut body)
+                    .map(|_| body)
+                    .map_err(|e| e.to_string())
+            }
+        };
+
+        match body {
+            Ok(body) => HttpResponse::Ok()
+                .content_type(self.content_type())
+                .body(body),
+            Err(reason) => internal_server_error(
+                format!("Failed to encode response: {reason}"),
+                "ENCODING_ERROR".to_string(),
+            ),
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Json | Self::Flat => "application/json",
+            Self::Csv => CSV_CONTENT_TYPE,
+            Self::Ndjson => NDJSON_CONTENT_TYPE,
+            Self::MessagePack => MSGPACK_CONTENT_TYPE,
+            Self::Cbor => CBOR_CONTENT_TYPE,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct IpColumn {
+    ip: IpAddr,
+}
+
+/// One row per IP Address with an `ip` column followed by the `FlatLookupResult` fields.
+fn encode_csv(response: &LookupResponseModel) -> Result<Vec<u8>, String> {
+    let mut rows: Vec<_> = response.results.flatten().into_iter().collect();
+    rows.sort_by_key(|(ip, _)| *ip);
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+
+    for (ip, result) in rows {
+        writer
+            .serialize((IpColumn { ip }, result.unwrap_or_default()))
+            .map_err(|e| e.to_string())?;
+    }
+
+    writer.into_inner().map_err(|e| e.to_string())
+}
+
+/// One JSON object per line for each of the looked up IP Addresses. Overridden results have the
+/// overlay network in `overlay`.
+fn encode_ndjson(response: &LookupResponseModel) -> Result<Vec<u8>, String> {
+    let Value::Object(results) =
+        serde_json::to_value(&response.results).map_err(|e| e.to_string())?
+    else {
+        return Err("results are not a map".to_string());
+    };
+
+    let mut body = Vec::new();
+
+    for (ip, result) in results {
+        let mut line = json!({
+            "ip": ip,
+            "result": result,
+            "database_build_epoch": response.database_build_epoch,
+        });
+        if let Some(network) = response.overrides.get(&ip) {
+            line["overlay"] = json!(network);
+        }
+
+        serde_json::to_writer(&mut body, &line).map_err(|e| e.to_string())?;
+        body.push(b'\n');
+    }
+
+    Ok(body)
+}
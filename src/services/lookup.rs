@@ -1,11 +1,22 @@
-use super::bad_request;
+use super::formats::ResponseFormat;
+use super::{bad_request, internal_server_error};
 use crate::maxmind_db::MaxmindDB;
 use crate::models::{LookupResponseModel, LookupResult};
 use crate::network_utils::SpecialIPCheck;
 
-use actix_web::{HttpResponse, Responder, get, web};
+use actix_web::{HttpRequest, HttpResponse, Responder, get, web};
+use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::net::IpAddr;
 
+/// Maximum number of IP Addresses which can be looked up in a single request
+pub const MAX_IPS_PER_LOOKUP: usize = 50;
+
+#[derive(Deserialize)]
+pub(super) struct LookupQuery {
+    pub(super) format: Option<String>,
+}
+
 /// Lookup information on many IP addresses at once
 ///
 /// ## Path Parameters
@@ -31,28 +42,75 @@ use std::net::IpAddr;
 ///
 /// * `isp`
 ///
+/// * `raw`: The record as stored in the database. Supports databases of any vendor.
+///
 /// ### IP or IP Addresses (`ip_addresses`)
 ///
 /// Either a single IP Address (V4 or V6) or a list of comma (`,`) separated IP Addresses.
 ///
 /// Example: `1.1.1.1,2.2.2.2`
+///
+/// ## Query Parameters
+///
+/// ### Format (`format`)
+///
+/// Optional format of the response. Must be one of the below values. When not set, the format is
+/// negotiated using the `Accept` header (`application/json`, `text/csv`, `application/x-ndjson`,
+/// `application/msgpack` or `application/cbor`) and defaults to `default`.
+///
+/// * `default` (or `json`): The nested MaxMind record structure of the lookup type.
+///
+/// * `flat`: A flat object with the same fields for all lookup types (See `FlatLookupResult`).
+///   Fields which are not available in the database are `null`.
+///
+/// * `csv`: A CSV document with a header row and one row per IP Address. Columns are `ip`
+///   followed by the `FlatLookupResult` fields.
+///
+/// * `ndjson`: Newline delimited JSON with one `{"ip", "result", "database_build_epoch"}` object
+///   per IP Address. Overridden results also have the overlay network in `overlay`.
+///
+/// * `msgpack`: The `default` response encoded as MessagePack.
+///
+/// * `cbor`: The `default` response encoded as CBOR.
+///
+/// ## Overlay
+///
+/// When an overlay is configured, the results of IP Addresses in its networks are merged with or
+/// replaced by the overlay fields. `overrides` maps these IP Addresses to their overlay network.
 #[utoipa::path(
     get,
     path = "/geoip/lookup/{lookup_type}/{ip_addresses}",
     operation_id = "lookup",
     tag = "GeoIP",
     responses(
-        (status = 200, description = "Ok", body = LookupResponseModel)
+        (status = 200, description = "Ok", content(
+            (LookupResponseModel = "application/json"),
+            (String = "text/csv"),
+            (String = "application/x-ndjson"),
+            (Vec<u8> = "application/msgpack"),
+            (Vec<u8> = "application/cbor")
+        ))
     ),
     params(
         ("lookup_type" = String, Path, description = "Type of the lookup", example = "city"),
-        ("ip_addresses" = String, Path, description = "List of ip addresses separated by comma", example = "4.2.2.4")
+        ("ip_addresses" = String, Path, description = "List of ip addresses separated by comma", example = "4.2.2.4"),
+        ("format" = Option<String>, Query, description = "Format of the response", example = "flat")
     )
 )]
 #[get("/geoip/lookup/{lookup_type}/{ip_addresses}")]
-async fn handle(data: web::Data<MaxmindDB>, path: web::Path<(String, String)>) -> impl Responder {
+async fn handle(
+    req: HttpRequest,
+    data: web::Data<MaxmindDB>,
+    path: web::Path<(String, String)>,
+    query: web::Query<LookupQuery>,
+) -> impl Responder {
     let (lookup_type, ip_addresses) = path.into_inner();
 
+    let format = match ResponseFormat::negotiate(query.format.as_deref(), &req) {
+        Ok(format) => format,
+        Err(resp) => return resp,
+    };
+
     let ip_addresses: Vec<IpAddr> = match ip_addresses
         .split(',')
         .map(str::trim)
@@ -70,13 +128,23 @@ async fn handle(data: web::Data<MaxmindDB>, path: web::Path<(String, String)>) -
         Err(e) => return e,
     };
 
-    if ip_addresses.len() > 50 {
+    if ip_addresses.len() > MAX_IPS_PER_LOOKUP {
         return bad_request(
             "Too many IP Addresses".to_string(),
             "TOO_MANY_IPS".to_string(),
         );
     }
 
+    lookup(&data, &lookup_type, ip_addresses, format).await
+}
+
+/// Looks up the IP Addresses in the database and encodes the response in the requested format.
+pub(super) async fn lookup(
+    data: &MaxmindDB,
+    lookup_type: &str,
+    ip_addresses: Vec<IpAddr>,
+    format: ResponseFormat,
+) -> HttpResponse {
     let ip_addresses = match ip_addresses
         .iter()
         .map(|&ip| {
@@ -97,25 +165,38 @@ async fn handle(data: web::Data<MaxmindDB>, path: web::Path<(String, String)>) -
 
     let db_inner = data.db.read().await;
 
-    let results: LookupResult = match lookup_type.as_str() {
-        "anonymous_ip" => LookupResult::AnonymousIp(db_inner.lookup(ip_addresses).await),
-        "asn" => LookupResult::Asn(db_inner.lookup(ip_addresses).await),
-        "city" => LookupResult::City(db_inner.lookup(ip_addresses).await),
-        "connection_type" => LookupResult::ConnectionType(db_inner.lookup(ip_addresses).await),
-        "country" => LookupResult::Country(db_inner.lookup(ip_addresses).await),
-        "density_income" => LookupResult::DensityIncome(db_inner.lookup(ip_addresses).await),
-        "enterprise" => LookupResult::Enterprise(db_inner.lookup(ip_addresses).await),
-        "isp" => LookupResult::Isp(db_inner.lookup(ip_addresses).await),
-        _ => {
-            return bad_request(
-                "invalid lookup_type".to_string(),
-                "INVALID_LOOKUP_TYPE".to_string(),
-            );
-        }
+    let Some(results) = db_inner.lookup_by_type(lookup_type, ip_addresses).await else {
+        return bad_request(
+            "invalid lookup_type".to_string(),
+            "INVALID_LOOKUP_TYPE".to_string(),
+        );
+    };
+
+    let results = if format.is_flat() {
+        LookupResult::Flat(results.flatten())
+    } else {
+        results
+    };
+
+    let (results, overrides) = match data.overlay() {
+        Some(overlay) => match overlay.apply(results) {
+            Ok(applied) => applied,
+            Err(reason) => {
+                return internal_server_error(
+                    format!("Failed to apply overlay: {reason}"),
+                    "OVERLAY_ERROR".to_string(),
+                );
+            }
+        },
+        None => (results, BTreeMap::new()),
     };
 
-    HttpResponse::Ok().json(LookupResponseModel {
+    format.respond(&LookupResponseModel {
         results,
         database_build_epoch: db_inner.build_epoch(),
+        overrides: overrides
+            .into_iter()
+            .map(|(ip, network)| (ip.to_string(), network.to_string()))
+            .collect(),
     })
 }
@@ -0,0 +1,82 @@
+use crate::maxmind_db::MaxmindDB;
+use crate::mirror::MirrorConfig;
+use crate::services::{internal_server_error, not_found, service_unavailable, unauthorized};
+
+use actix_web::http::header::{
+    self, ContentDisposition, DispositionParam, DispositionType, EntityTag, HttpDate,
+};
+use actix_web::{HttpRequest, HttpResponse, Responder, route, web};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Serves the loaded database as a `.tar.gz` archive for other Atlas instances using the `mirror`
+/// database provider. Responds with `304 Not Modified` when the `If-None-Match` header matches
+/// the loaded database.
+#[route("/mirror/{variant}", method = "GET", method = "HEAD")]
+pub async fn handle(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<MaxmindDB>,
+    mirror: web::Data<MirrorConfig>,
+) -> impl Responder {
+    if !mirror.is_authorized(req.headers()) {
+        return unauthorized(
+            "Missing or invalid mirror token".to_string(),
+            "UNAUTHORIZED".to_string(),
+        );
+    }
+
+    if path.into_inner() != data.variant {
+        return not_found(
+            "Database variant is not served by this mirror".to_string(),
+            "VARIANT_NOT_FOUND".to_string(),
+        );
+    }
+
+    // Instances should keep retrying rather than replace their database with the fallback one
+    if data.db.read().await.is_fallback() {
+        return service_unavailable(
+            "Only the fallback database is loaded".to_string(),
+            "NOT_READY".to_string(),
+        );
+    }
+
+    let archive = match mirror.archive(&data).await {
+        Ok(archive) => archive,
+        Err(error) => {
+            return internal_server_error(
+                format!("Failed to build database archive: {error}"),
+                "MIRROR_ERROR".to_string(),
+            );
+        }
+    };
+
+    let etag = EntityTag::new_strong(archive.build_epoch.to_string());
+    let last_modified = HttpDate::from(UNIX_EPOCH + Duration::from_secs(archive.build_epoch));
+
+    let not_modified = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value == etag.to_string());
+
+    let mut builder = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+
+    builder
+        .insert_header(header::ETag(etag))
+        .insert_header(header::LastModified(last_modified))
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(archive.filename)],
+        })
+        .content_type("application/gzip");
+
+    if not_modified || req.method() == actix_web::http::Method::HEAD {
+        builder.finish()
+    } else {
+        builder.body(archive.data)
+    }
+}
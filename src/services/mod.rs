@@ -1,8 +1,14 @@
-use actix_web::HttpResponse;
+use actix_web::http::header;
+use actix_web::{HttpResponse, HttpResponseBuilder};
 use serde::Serialize;
+use std::time::Duration;
 
+pub mod admin;
+mod formats;
 pub mod healthcheck;
 pub mod lookup;
+pub mod mirror;
+pub mod whoami;
 
 #[derive(Serialize)]
 struct Error {
@@ -15,8 +21,44 @@ struct ErrorResponse {
     error: Error,
 }
 
-pub fn bad_request(message: String, code: String) -> HttpResponse {
-    HttpResponse::BadRequest().json(ErrorResponse {
+fn error_response(mut builder: HttpResponseBuilder, message: String, code: String) -> HttpResponse {
+    builder.json(ErrorResponse {
         error: Error { message, code },
     })
 }
+
+pub fn bad_request(message: String, code: String) -> HttpResponse {
+    error_response(HttpResponse::BadRequest(), message, code)
+}
+
+pub fn internal_server_error(message: String, code: String) -> HttpResponse {
+    error_response(HttpResponse::InternalServerError(), message, code)
+}
+
+pub fn not_found(message: String, code: String) -> HttpResponse {
+    error_response(HttpResponse::NotFound(), message, code)
+}
+
+pub fn unauthorized(message: String, code: String) -> HttpResponse {
+    error_response(HttpResponse::Unauthorized(), message, code)
+}
+
+pub fn service_unavailable(message: String, code: String) -> HttpResponse {
+    error_response(HttpResponse::ServiceUnavailable(), message, code)
+}
+
+/// Without `retry_after` when retrying will not help
+pub fn too_many_requests(
+    message: String,
+    code: String,
+    retry_after: Option<Duration>,
+) -> HttpResponse {
+    let mut builder = HttpResponse::TooManyRequests();
+    if let Some(retry_after) = retry_after {
+        // Retry-After is in whole seconds, round up so that clients do not retry too early
+        let retry_after = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        builder.insert_header((header::RETRY_AFTER, retry_after));
+    }
+
+    error_response(builder, message, code)
+}
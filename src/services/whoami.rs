@@ -0,0 +1,68 @@
+use super::bad_request;
+use super::formats::ResponseFormat;
+use super::lookup::{LookupQuery, lookup};
+use crate::maxmind_db::MaxmindDB;
+use crate::models::LookupResponseModel;
+use crate::network_utils::ClientIpConfig;
+
+use actix_web::{HttpRequest, Responder, get, web};
+
+/// Lookup information on the IP address of the caller
+///
+/// The IP address is the peer address of the connection. When the request is coming from one of
+/// the trusted proxies (`TRUSTED_PROXIES`), it is derived from the configured proxy headers
+/// (`CLIENT_IP_HEADERS`) instead.
+///
+/// ## Path Parameters
+///
+/// ### Lookup Type (`lookup_type`)
+///
+/// Type of the lookup. Same as the values supported by the `lookup` endpoint.
+///
+/// ## Query Parameters
+///
+/// ### Format (`format`)
+///
+/// Optional format of the response. Same as the values supported by the `lookup` endpoint.
+#[utoipa::path(
+    get,
+    path = "/geoip/lookup/{lookup_type}/me",
+    operation_id = "lookup_me",
+    tag = "GeoIP",
+    responses(
+        (status = 200, description = "Ok", body = LookupResponseModel)
+    ),
+    params(
+        ("lookup_type" = String, Path, description = "Type of the lookup", example = "city"),
+        ("format" = Option<String>, Query, description = "Format of the response", example = "flat")
+    )
+)]
+#[get("/geoip/lookup/{lookup_type}/me")]
+async fn handle(
+    req: HttpRequest,
+    data: web::Data<MaxmindDB>,
+    client_ip_config: Option<web::Data<ClientIpConfig>>,
+    path: web::Path<String>,
+    query: web::Query<LookupQuery>,
+) -> impl Responder {
+    let lookup_type = path.into_inner();
+
+    let format = match ResponseFormat::negotiate(query.format.as_deref(), &req) {
+        Ok(format) => format,
+        Err(resp) => return resp,
+    };
+
+    let client_ip = match client_ip_config {
+        Some(config) => config.client_ip(&req),
+        None => ClientIpConfig::default().client_ip(&req),
+    };
+
+    let Some(client_ip) = client_ip else {
+        return bad_request(
+            "Could not determine the client IP Address".to_string(),
+            "UNKNOWN_CLIENT_IP".to_string(),
+        );
+    };
+
+    lookup(&data, &lookup_type, vec![client_ip], format).await
+}
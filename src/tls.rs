@@ -0,0 +1,142 @@
+use rustls::crypto::aws_lc_rs;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// When set, clients must present a certificate signed by one of the CAs in this file.
+    pub client_ca_path: Option<PathBuf>,
+    /// How often to check the certificate and key files for changes.
+    pub reload_interval: Duration,
+}
+
+impl TlsConfig {
+    /// Builds a rustls server config which serves the certificate of `resolver`.
+    pub fn server_config(
+        &self,
+        resolver: Arc<ReloadingCertResolver>,
+    ) -> Result<ServerConfig, Box<dyn Error>> {
+        let provider = Arc::new(aws_lc_rs::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+
+        let builder = match &self.client_ca_path {
+            Some(client_ca_path) => {
+                let mut roots = RootCertStore::empty();
+
+                for cert in CertificateDer::pem_file_iter(client_ca_path)? {
+                    roots.add(cert?)?;
+                }
+
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .build()?;
+
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let mut config = builder.with_cert_resolver(resolver);
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(config)
+    }
+}
+
+/// Serves a certificate loaded from disk which can be reloaded without restarting the server.
+#[derive(Debug)]
+pub struct ReloadingCertResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<LoadedCert>,
+}
+
+#[derive(Debug)]
+struct LoadedCert {
+    certified_key: Arc<CertifiedKey>,
+    modified: (SystemTime, SystemTime),
+}
+
+impl ReloadingCertResolver {
+    pub fn load(cert_path: &Path, key_path: &Path) -> Result<Self, Box<dyn Error>> {
+        let current = Self::load_cert(cert_path, key_path)?;
+
+        Ok(Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            current: RwLock::new(current),
+        })
+    }
+
+    fn load_cert(cert_path: &Path, key_path: &Path) -> Result<LoadedCert, Box<dyn Error>> {
+        let modified = Self::modified_times(cert_path, key_path)?;
+
+        let certs = CertificateDer::pem_file_iter(cert_path)?.collect::<Result<Vec<_>, _>>()?;
+        let key = PrivateKeyDer::from_pem_file(key_path)?;
+        let certified_key = CertifiedKey::from_der(certs, key, &aws_lc_rs::default_provider())?;
+
+        Ok(LoadedCert {
+            certified_key: Arc::new(certified_key),
+            modified,
+        })
+    }
+
+    fn modified_times(
+        cert_path: &Path,
+        key_path: &Path,
+    ) -> Result<(SystemTime, SystemTime), Box<dyn Error>> {
+        Ok((
+            std::fs::metadata(cert_path)?.modified()?,
+            std::fs::metadata(key_path)?.modified()?,
+        ))
+    }
+
+    /// Reloads the certificate and key when either of the files was modified since the last load.
+    /// Returns whether a new certificate was loaded. On failure the current certificate is kept.
+    pub fn reload_if_changed(&self) -> Result<bool, Box<dyn Error>> {
+        let modified = Self::modified_times(&self.cert_path, &self.key_path)?;
+
+        if self.current.read().unwrap().modified == modified {
+            return Ok(false);
+        }
+
+        let new_cert = Self::load_cert(&self.cert_path, &self.key_path)?;
+        *self.current.write().unwrap() = new_cert;
+
+        Ok(true)
+    }
+
+    /// The certificate chain which is currently being served.
+    pub fn certificate_chain(&self) -> Vec<CertificateDer<'static>> {
+        self.current.read().unwrap().certified_key.cert.clone()
+    }
+
+    /// Periodically checks the certificate files for changes and reloads them.
+    pub async fn watch(self: Arc<Self>, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+
+            match self.reload_if_changed() {
+                Ok(true) => println!("Reloaded TLS certificate {:?}", self.cert_path),
+                Ok(false) => {}
+                Err(reason) => println!("Failed to reload TLS certificate {reason:?}"),
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().certified_key.clone())
+    }
+}
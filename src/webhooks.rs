@@ -0,0 +1,143 @@
+use crate::download_utils::{RetryPolicy, send_with_retries};
+
+use hmac::{Hmac, Mac};
+use reqwest::header::CONTENT_TYPE;
+use serde::Serialize;
+use sha2::Sha256;
+use std::{env, error::Error, fmt, time::Duration};
+
+/// Header carrying the HMAC-SHA256 signature of the request body, `sha256={hex}`
+pub const SIGNATURE_HEADER: &str = "X-Atlas-Signature";
+/// Header carrying the event type
+pub const EVENT_HEADER: &str = "X-Atlas-Event";
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Event sent to the webhooks after an update swapped the database or failed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UpdateEvent {
+    /// `database.updated` or `database.update_failed`
+    pub event: &'static str,
+    pub edition: String,
+    pub success: bool,
+    pub old_build_epoch: u64,
+    pub new_build_epoch: u64,
+    /// Directory of the loaded database
+    pub directory: String,
+    pub duration_ms: u64,
+    /// Why the update failed
+    pub reason: Option<String>,
+}
+
+impl UpdateEvent {
+    pub const UPDATED: &str = "database.updated";
+    pub const UPDATE_FAILED: &str = "database.update_failed";
+}
+
+/// URLs update events are posted to
+pub struct Webhooks {
+    urls: Vec<String>,
+    secret: Option<String>,
+    client: reqwest::Client,
+    retry: RetryPolicy,
+}
+
+impl fmt::Debug for Webhooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Webhooks")
+            .field("urls", &self.urls)
+            .field("signed", &self.secret.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Webhooks {
+    pub fn new(
+        urls: Vec<String>,
+        secret: Option<String>,
+        retry: RetryPolicy,
+    ) -> Result<Self, Box<dyn Error>> {
+        let client = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .user_agent(format!("atlas/{}", env!("CARGO_PKG_VERSION")))
+            .build()?;
+
+        Ok(Self {
+            urls,
+            secret,
+            client,
+            retry,
+        })
+    }
+
+    /// Reads `WEBHOOK_URLS` (comma separated), `WEBHOOK_SECRET` and `WEBHOOK_MAX_ATTEMPTS`.
+    /// Returns `None` when no URLs are configured.
+    pub fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        let urls: Vec<String> = env::var("WEBHOOK_URLS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .map(String::from)
+            .collect();
+
+        if urls.is_empty() {
+            return Ok(None);
+        }
+
+        let mut retry = RetryPolicy::default();
+        if let Ok(max_attempts) = env::var("WEBHOOK_MAX_ATTEMPTS") {
+            retry.max_attempts = max_attempts
+                .parse()
+                .map_err(|_| "Invalid WEBHOOK_MAX_ATTEMPTS value")?;
+        }
+
+        Self::new(urls, env::var("WEBHOOK_SECRET").ok(), retry).map(Some)
+    }
+
+    /// Posts the event to all webhooks. Failed deliveries are logged and otherwise ignored.
+    pub async fn send(&self, event: &UpdateEvent) {
+        let body = match serde_json::to_vec(event) {
+            Ok(body) => body,
+            Err(error) => {
+                println!("Failed to serialize webhook event: {error}");
+                return;
+            }
+        };
+
+        for url in &self.urls {
+            let mut request = self
+                .client
+                .post(url)
+                .header(CONTENT_TYPE, "application/json")
+                .header(EVENT_HEADER, event.event)
+                .body(body.clone());
+
+            if let Some(secret) = &self.secret {
+                request = request.header(SIGNATURE_HEADER, signature(secret, &body));
+            }
+
+            match send_with_retries(&request, &self.retry, "webhook").await {
+                Ok(response) if response.status().is_success() => {}
+                Ok(response) => println!("Webhook {url} responded with {}", response.status()),
+                Err(error) => println!("Failed to send webhook to {url}: {error}"),
+            }
+        }
+    }
+}
+
+/// Signature of `body` with `secret` as sent in [`SIGNATURE_HEADER`]
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+
+    let digest: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+
+    format!("sha256={digest}")
+}
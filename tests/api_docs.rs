@@ -0,0 +1,16 @@
+#[test]
+fn test_lookup_result_documents_raw_records() {
+    let spec = serde_json::to_value(atlas_rs::api_docs::api_doc()).unwrap();
+    let lookup_results =
+        &spec["components"]["schemas"]["LookupResult"]["additionalProperties"]["oneOf"];
+
+    let raw = lookup_results
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|schema| schema["title"] == "RawLookupResult")
+        .expect("RawLookupResult schema is missing");
+
+    assert_eq!(raw["type"], "object");
+    assert_eq!(raw["additionalProperties"], true);
+}
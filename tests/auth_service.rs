@@ -0,0 +1,179 @@
+use actix_http::body::MessageBody;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::{App, http::StatusCode, middleware, test, web::Data};
+use atlas_rs::auth::{self, ApiKeyStore};
+
+mod common;
+
+use common::load_test_db;
+
+async fn setup(
+    keys: &str,
+) -> impl Service<
+    actix_http::Request,
+    Error = actix_web::Error,
+    Response = ServiceResponse<impl MessageBody>,
+> {
+    let app_data = load_test_db().await;
+    let api_keys = ApiKeyStore::load(Some(keys), None).await.unwrap().unwrap();
+
+    test::init_service(
+        App::new()
+            .app_data(app_data)
+            .app_data(Data::new(api_keys))
+            .wrap(middleware::from_fn(auth::require_api_key))
+            .service(atlas_rs::services::lookup::handle)
+            .service(atlas_rs::services::healthcheck::handle),
+    )
+    .await
+}
+
+#[actix_web::test]
+async fn test_rejects_missing_api_key() {
+    let service = setup("secret").await;
+    let req = test::TestRequest::get()
+        .uri("/geoip/lookup/city/214.78.120.1")
+        .to_request();
+
+    let resp = test::call_service(&service, req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let resp: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(resp["error"]["code"], "UNAUTHORIZED");
+}
+
+#[actix_web::test]
+async fn test_rejects_percent_encoded_path_without_api_key() {
+    let service = setup("secret").await;
+    let req = test::TestRequest::get()
+        .uri("/%67eoip/lookup/city/214.78.120.1")
+        .to_request();
+
+    let resp = test::call_service(&service, req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn test_rejects_entries_without_key() {
+    for keys in [":600", ":600:1000", "secret, :600"] {
+        let error = ApiKeyStore::load(Some(keys), None).await.unwrap_err();
+        assert!(error.to_string().starts_with("Missing API key"), "{keys}");
+    }
+
+    // Empty entries are still skipped
+    assert!(ApiKeyStore::load(Some("secret,,"), None).await.is_ok());
+}
+
+#[actix_web::test]
+async fn test_rejects_zero_limits() {
+    let error = ApiKeyStore::load(Some("secret:0"), None).await.unwrap_err();
+    assert_eq!(error.to_string(), "Invalid API key rate limit");
+
+    let error = ApiKeyStore::load(Some("secret:10:0"), None)
+        .await
+        .unwrap_err();
+    assert_eq!(error.to_string(), "Invalid API key daily quota");
+}
+
+#[actix_web::test]
+async fn test_rejects_invalid_api_key() {
+    let service = setup("secret").await;
+    let req = test::TestRequest::get()
+        .uri("/geoip/lookup/city/214.78.120.1")
+        .insert_header(("X-API-Key", "not-a-secret"))
+        .to_request();
+
+    let resp = test::call_service(&service, req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn test_accepts_api_key_in_header_and_query() {
+    let service = setup("secret,other").await;
+
+    let req = test::TestRequest::get()
+        .uri("/geoip/lookup/city/214.78.120.1")
+        .insert_header(("X-API-Key", "secret"))
+        .to_request();
+    assert!(
+        test::call_service(&service, req)
+            .await
+            .status()
+            .is_success()
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/geoip/lookup/city/214.78.120.1?api_key=other")
+        .to_request();
+    assert!(
+        test::call_service(&service, req)
+            .await
+            .status()
+            .is_success()
+    );
+}
+
+#[actix_web::test]
+async fn test_healthcheck_does_not_require_api_key() {
+    let service = setup("secret").await;
+    let req = test::TestRequest::get().uri("/health").to_request();
+
+    assert!(
+        test::call_service(&service, req)
+            .await
+            .status()
+            .is_success()
+    );
+}
+
+#[actix_web::test]
+async fn test_rate_limited_api_key() {
+    let service = setup("secret:2").await;
+
+    for _ in 0..2 {
+        let req = test::TestRequest::get()
+            .uri("/geoip/lookup/city/214.78.120.1?api_key=secret")
+            .to_request();
+        assert!(
+            test::call_service(&service, req)
+                .await
+                .status()
+                .is_success()
+        );
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/geoip/lookup/city/214.78.120.1?api_key=secret")
+        .to_request();
+    let resp = test::call_service(&service, req).await;
+
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(resp.headers().contains_key("retry-after"));
+
+    let resp: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(resp["error"]["code"], "RATE_LIMITED");
+}
+
+#[actix_web::test]
+async fn test_daily_quota_exceeded() {
+    let service = setup("secret::1").await;
+
+    let req = test::TestRequest::get()
+        .uri("/geoip/lookup/city/214.78.120.1?api_key=secret")
+        .to_request();
+    assert!(
+        test::call_service(&service, req)
+            .await
+            .status()
+            .is_success()
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/geoip/lookup/city/214.78.120.1?api_key=secret")
+        .to_request();
+    let resp = test::call_service(&service, req).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    let resp: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(resp["error"]["code"], "QUOTA_EXCEEDED");
+}
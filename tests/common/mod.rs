@@ -0,0 +1,34 @@
+//! Fixtures shared by the integration tests. Not every test uses all of them.
+#![allow(dead_code)]
+
+use actix_web::web;
+use atlas_rs::maxmind_db::{DbLayout, MaxmindDB};
+use atlas_rs::providers::DbProvider;
+use std::path::Path;
+
+pub const TEST_DB: &str = "tests-data/GeoIP2-City-Test_1/GeoIP2-City-Test.mmdb";
+pub const VARIANT: &str = "GeoIP2-City-Test";
+
+/// Copies the test database into `dir` as the database of [`VARIANT`]
+pub fn copy_test_db(dir: &Path) {
+    std::fs::create_dir_all(dir).unwrap();
+    std::fs::copy(TEST_DB, dir.join(format!("{VARIANT}.mmdb"))).unwrap();
+}
+
+/// Loads the database of `base_path` like a separate process sharing the path would
+pub async fn init_db(base_path: &Path, provider: DbProvider) -> MaxmindDB {
+    MaxmindDB::init_with_provider(
+        VARIANT,
+        base_path.to_str().unwrap(),
+        provider,
+        DbLayout::Versioned,
+    )
+    .await
+    .unwrap()
+}
+
+/// Loads the tracked test database as app data. The local provider neither locks nor cleans up
+/// `tests-data/`.
+pub async fn load_test_db() -> web::Data<MaxmindDB> {
+    web::Data::new(init_db(Path::new("tests-data/"), DbProvider::Local).await)
+}
@@ -0,0 +1,440 @@
+use actix_web::{App, test as actix_test, web};
+use atlas_rs::admin::AdminConfig;
+use atlas_rs::db_diff::{self, ChangeKind, CountryCounts, DiffFormat, NetworkChange};
+use atlas_rs::maxmind_db::{self, DbLayout, MaxmindDB};
+use atlas_rs::providers::DbProvider;
+use std::net::Ipv4Addr;
+use std::path::Path;
+
+const TEST_DB: &str = "tests-data/GeoIP2-City-Test_1/GeoIP2-City-Test.mmdb";
+const VARIANT: &str = "GeoIP2-City-Test";
+const TOKEN: &str = "admin-secret";
+
+/// Network of a test database with its country and ASN
+type TestNetwork = (&'static str, Option<&'static str>, Option<u32>);
+
+const OLD_NETWORKS: &[TestNetwork] = &[
+    ("1.0.0.0/24", Some("US"), Some(15169)),
+    ("2.0.0.0/24", Some("DE"), None),
+    ("3.0.0.0/24", Some("FR"), None),
+    ("5.0.0.0/23", Some("DE"), Some(3320)),
+];
+
+const NEW_NETWORKS: &[TestNetwork] = &[
+    // Split with the same data
+    ("1.0.0.0/25", Some("US"), Some(15169)),
+    ("1.0.0.128/25", Some("US"), Some(15169)),
+    ("2.0.0.0/24", Some("FR"), None),
+    ("4.0.0.0/24", Some("GB"), None),
+    ("5.0.1.0/24", Some("DE"), Some(3320)),
+];
+
+/// Writes a minimal IPv4 MaxMind database with a record per network
+fn write_test_db(path: &Path, networks: &[TestNetwork]) {
+    // Data section
+    let mut data = Vec::new();
+    let mut offsets = Vec::new();
+    for (_, country, asn) in networks {
+        offsets.push(data.len());
+        let fields = country.is_some() as u8 + asn.is_some() as u8;
+        data.push(0xE0 | fields);
+        if let Some(country) = country {
+            write_string(&mut data, "country");
+            data.push(0xE1);
+            write_string(&mut data, "iso_code");
+            write_string(&mut data, country);
+        }
+        if let Some(asn) = asn {
+            write_string(&mut data, "autonomous_system_number");
+            data.push(0xC4);
+            data.extend(asn.to_be_bytes());
+        }
+    }
+
+    // Search tree, records are node indexes or `None` until the data pointers are known
+    let mut nodes: Vec<[Option<usize>; 2]> = vec![[None, None]];
+    let mut leaves: Vec<(usize, usize, usize)> = Vec::new();
+    for (index, (network, _, _)) in networks.iter().enumerate() {
+        let (ip, prefix) = network.split_once('/').unwrap();
+        let ip = u32::from(ip.parse::<Ipv4Addr>().unwrap());
+        let prefix: usize = prefix.parse().unwrap();
+
+        let mut node = 0;
+        for depth in 0..prefix {
+            let bit = ((ip >> (31 - depth)) & 1) as usize;
+            if depth == prefix - 1 {
+                leaves.push((node, bit, index));
+            } else {
+                node = match nodes[node][bit] {
+                    Some(child) => child,
+                    None => {
+                        nodes.push([None, None]);
+                        nodes[node][bit] = Some(nodes.len() - 1);
+                        nodes.len() - 1
+                    }
+                };
+            }
+        }
+    }
+
+    let node_count = nodes.len();
+    let mut records: Vec<[usize; 2]> = nodes
+        .iter()
+        .map(|node| node.map(|child| child.unwrap_or(node_count)))
+        .collect();
+    for (node, bit, index) in leaves {
+        records[node][bit] = node_count + 16 + offsets[index];
+    }
+
+    let mut db = Vec::new();
+    for record in records.iter().flatten() {
+        db.extend(&(*record as u32).to_be_bytes()[1..]);
+    }
+    db.extend([0; 16]);
+    db.extend(data);
+
+    // Metadata
+    db.extend(b"\xAB\xCD\xEFMaxMind.com");
+    db.push(0xE9);
+    write_string(&mut db, "node_count");
+    db.push(0xC4);
+    db.extend((node_count as u32).to_be_bytes());
+    write_string(&mut db, "record_size");
+    db.extend([0xA1, 24]);
+    write_string(&mut db, "ip_version");
+    db.extend([0xA1, 4]);
+    write_string(&mut db, "database_type");
+    write_string(&mut db, VARIANT);
+    write_string(&mut db, "languages");
+    db.extend([0x00, 0x04]);
+    write_string(&mut db, "description");
+    db.push(0xE0);
+    write_string(&mut db, "binary_format_major_version");
+    db.extend([0xA1, 2]);
+    write_string(&mut db, "binary_format_minor_version");
+    db.push(0xA0);
+    write_string(&mut db, "build_epoch");
+    db.extend([0x04, 0x02]);
+    db.extend(1_700_000_000u32.to_be_bytes());
+
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, db).unwrap();
+}
+
+fn write_string(buf: &mut Vec<u8>, value: &str) {
+    buf.push(0x40 | value.len() as u8);
+    buf.extend(value.as_bytes());
+}
+
+fn change(
+    change: ChangeKind,
+    network: &str,
+    (old_country, old_asn): (Option<&str>, Option<u32>),
+    (new_country, new_asn): (Option<&str>, Option<u32>),
+) -> NetworkChange {
+    NetworkChange {
+        change,
+        network: network.to_string(),
+        old_country: old_country.map(String::from),
+        new_country: new_country.map(String::from),
+        old_asn,
+        new_asn,
+    }
+}
+
+fn counts(
+    country: &str,
+    old_networks: u64,
+    new_networks: u64,
+    gained: u64,
+    lost: u64,
+) -> CountryCounts {
+    CountryCounts {
+        country: country.to_string(),
+        old_networks,
+        new_networks,
+        gained,
+        lost,
+    }
+}
+
+#[test]
+fn test_diff_of_same_database_is_empty() {
+    let report = db_diff::diff(
+        Path::new(TEST_DB),
+        Path::new("tests-data/GeoIP2-City-Test_1"),
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(report.old.database_type, "GeoIP2-City");
+    assert!(report.old.networks > 0);
+    assert_eq!(report.old.networks, report.new.networks);
+    assert_eq!((report.changed, report.added, report.removed), (0, 0, 0));
+    assert!(report.networks.is_empty());
+    assert!(!report.countries.is_empty());
+    assert!(report.countries.iter().all(|counts| {
+        counts.old_networks == counts.new_networks && counts.gained == 0 && counts.lost == 0
+    }));
+}
+
+#[test]
+fn test_diff_reports_changed_added_and_removed_networks() {
+    let dir = tempfile::tempdir().unwrap();
+    let old_db = dir.path().join("old.mmdb");
+    let new_db = dir.path().join("new.mmdb");
+    write_test_db(&old_db, OLD_NETWORKS);
+    write_test_db(&new_db, NEW_NETWORKS);
+
+    let report = db_diff::diff(&old_db, &new_db, None).unwrap();
+
+    assert_eq!(report.old.networks, 4);
+    assert_eq!(report.new.networks, 5);
+    assert_eq!((report.changed, report.added, report.removed), (1, 1, 2));
+    assert_eq!(
+        report.networks,
+        vec![
+            change(
+                ChangeKind::Changed,
+                "2.0.0.0/24",
+                (Some("DE"), None),
+                (Some("FR"), None)
+            ),
+            change(
+                ChangeKind::Removed,
+                "3.0.0.0/24",
+                (Some("FR"), None),
+                (None, None)
+            ),
+            change(
+                ChangeKind::Added,
+                "4.0.0.0/24",
+                (None, None),
+                (Some("GB"), None)
+            ),
+            change(
+                ChangeKind::Removed,
+                "5.0.0.0/24",
+                (Some("DE"), Some(3320)),
+                (None, None)
+            ),
+        ]
+    );
+    assert_eq!(
+        report.countries,
+        vec![
+            counts("DE", 2, 1, 0, 2),
+            counts("FR", 1, 1, 1, 1),
+            counts("GB", 0, 1, 1, 0),
+            counts("US", 1, 2, 0, 0),
+        ]
+    );
+}
+
+#[test]
+fn test_diff_csv_formats() {
+    let dir = tempfile::tempdir().unwrap();
+    let old_db = dir.path().join("old.mmdb");
+    let new_db = dir.path().join("new.mmdb");
+    write_test_db(&old_db, OLD_NETWORKS);
+    write_test_db(&new_db, NEW_NETWORKS);
+    let report = db_diff::diff(&old_db, &new_db, None).unwrap();
+
+    let mut csv = Vec::new();
+    report.write(DiffFormat::Csv, &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("change,network,old_country,new_country,old_asn,new_asn")
+    );
+    assert_eq!(lines.next(), Some("changed,2.0.0.0/24,DE,FR,,"));
+    assert_eq!(lines.count(), 3);
+
+    let mut csv = Vec::new();
+    report.write(DiffFormat::CountryCsv, &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    assert_eq!(
+        csv.lines().take(2).collect::<Vec<_>>(),
+        vec![
+            "country,old_networks,new_networks,gained,lost",
+            "DE,2,1,0,2"
+        ]
+    );
+}
+
+#[test]
+fn test_diff_of_missing_database_fails() {
+    let error = db_diff::diff(
+        Path::new("tests-data/missing.mmdb"),
+        Path::new(TEST_DB),
+        None,
+    )
+    .unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .starts_with("Failed to open tests-data/missing.mmdb")
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let error = db_diff::diff(dir.path(), Path::new(TEST_DB), None).unwrap_err();
+    assert!(error.to_string().starts_with("No .mmdb file found in"));
+}
+
+#[test]
+fn test_db_file_resolves_variant() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_dir = dir.path().join(format!("{VARIANT}_20240101"));
+    write_test_db(&db_dir.join("Another.mmdb"), OLD_NETWORKS);
+    write_test_db(&db_dir.join(format!("{VARIANT}.mmdb")), NEW_NETWORKS);
+
+    // Taken from the name of versioned directories
+    assert_eq!(
+        maxmind_db::db_file(&db_dir, None).unwrap(),
+        db_dir.join(format!("{VARIANT}.mmdb"))
+    );
+    assert_eq!(
+        maxmind_db::db_file(&db_dir, Some("Another")).unwrap(),
+        db_dir.join("Another.mmdb")
+    );
+
+    let error = maxmind_db::db_file(dir.path(), None).unwrap_err();
+    assert!(error.to_string().starts_with("No .mmdb file found in"));
+
+    write_test_db(&dir.path().join("Another.mmdb"), OLD_NETWORKS);
+    write_test_db(&dir.path().join("Third.mmdb"), OLD_NETWORKS);
+    let error = maxmind_db::db_file(dir.path(), None).unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .starts_with("Several .mmdb files found in")
+    );
+}
+
+#[actix_web::test]
+async fn test_admin_diff_flat_layout() {
+    let dir = tempfile::tempdir().unwrap();
+    // Sorted before the database of the variant
+    write_test_db(&dir.path().join("Another.mmdb"), NEW_NETWORKS);
+    write_test_db(&dir.path().join("old.mmdb"), OLD_NETWORKS);
+    write_test_db(&dir.path().join(format!("{VARIANT}.mmdb")), NEW_NETWORKS);
+
+    let db = MaxmindDB::init_with_provider(
+        VARIANT,
+        dir.path().to_str().unwrap(),
+        DbProvider::Local,
+        DbLayout::Flat,
+    )
+    .await
+    .unwrap();
+    let service = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(db))
+            .app_data(web::Data::new(AdminConfig::new(TOKEN.to_string())))
+            .service(atlas_rs::services::admin::diff),
+    )
+    .await;
+
+    let req = actix_test::TestRequest::get()
+        .uri("/admin/diff")
+        .insert_header(("Authorization", format!("Bearer {TOKEN}")))
+        .to_request();
+    let resp = actix_test::call_service(&service, req).await;
+    assert_eq!(resp.status(), 400);
+    let resp: serde_json::Value = actix_test::read_body_json(resp).await;
+    assert!(resp.to_string().contains("OLD_DB_REQUIRED"));
+
+    let req = actix_test::TestRequest::get()
+        .uri("/admin/diff?old=old.mmdb")
+        .insert_header(("Authorization", format!("Bearer {TOKEN}")))
+        .to_request();
+    let resp: serde_json::Value = actix_test::call_and_read_body_json(&service, req).await;
+    assert!(
+        resp["new"]["path"]
+            .as_str()
+            .unwrap()
+            .ends_with(&format!("{VARIANT}.mmdb"))
+    );
+    assert_eq!(resp["changed"], 1);
+}
+
+#[actix_web::test]
+async fn test_admin_diff_endpoint() {
+    let dir = tempfile::tempdir().unwrap();
+    write_test_db(
+        &dir.path()
+            .join(format!("{VARIANT}_20240101/{VARIANT}.mmdb")),
+        OLD_NETWORKS,
+    );
+    write_test_db(
+        &dir.path()
+            .join(format!("{VARIANT}_20240102/{VARIANT}.mmdb")),
+        NEW_NETWORKS,
+    );
+
+    let db = MaxmindDB::init_with_provider(
+        VARIANT,
+        dir.path().to_str().unwrap(),
+        DbProvider::Local,
+        DbLayout::Versioned,
+    )
+    .await
+    .unwrap();
+    let service = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(db))
+            .app_data(web::Data::new(AdminConfig::new(TOKEN.to_string())))
+            .service(atlas_rs::services::admin::diff),
+    )
+    .await;
+
+    let req = actix_test::TestRequest::get()
+        .uri("/admin/diff")
+        .to_request();
+    assert_eq!(actix_test::call_service(&service, req).await.status(), 401);
+
+    // Compares the loaded database with the previous one by default
+    let req = actix_test::TestRequest::get()
+        .uri("/admin/diff")
+        .insert_header(("Authorization", format!("Bearer {TOKEN}")))
+        .to_request();
+    let resp: serde_json::Value = actix_test::call_and_read_body_json(&service, req).await;
+    assert!(
+        resp["old"]["path"]
+            .as_str()
+            .unwrap()
+            .ends_with(&format!("{VARIANT}_20240101/{VARIANT}.mmdb"))
+    );
+    assert_eq!(resp["changed"], 1);
+    assert_eq!(resp["networks"][0]["network"], "2.0.0.0/24");
+
+    let req = actix_test::TestRequest::get()
+        .uri(&format!(
+            "/admin/diff?old={VARIANT}_20240102&new={VARIANT}_20240101&format=csv"
+        ))
+        .insert_header(("Authorization", format!("Bearer {TOKEN}")))
+        .to_request();
+    let resp = actix_test::call_service(&service, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "text/csv");
+    let body = actix_test::read_body(resp).await;
+    assert!(body.starts_with(b"change,network,"));
+    assert!(String::from_utf8_lossy(&body).contains("changed,2.0.0.0/24,FR,DE,,"));
+
+    for (query, status) in [
+        ("old=../etc", 400),
+        ("old=missing", 404),
+        ("format=xml", 400),
+    ] {
+        let req = actix_test::TestRequest::get()
+            .uri(&format!("/admin/diff?{query}"))
+            .insert_header(("Authorization", format!("Bearer {TOKEN}")))
+            .to_request();
+        assert_eq!(
+            actix_test::call_service(&service, req).await.status(),
+            status,
+            "{query}"
+        );
+    }
+}
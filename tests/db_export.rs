@@ -0,0 +1,131 @@
+use atlas_rs::db_export::{self, ExportFormat};
+use atlas_rs::models::FlatLookupResult;
+use maxminddb::Reader;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::record::RowAccessor;
+
+const TEST_DB: &str = "tests-data/GeoIP2-City-Test_1/GeoIP2-City-Test.mmdb";
+
+fn export(fields: &[&str], format: ExportFormat) -> (u64, Vec<u8>) {
+    let reader = Reader::open_readfile(TEST_DB).unwrap();
+    let mut output = Vec::new();
+    let count = db_export::export(&reader, fields, format, &mut output).unwrap();
+
+    (count, output)
+}
+
+#[test]
+fn test_fields_are_flat_lookup_result_fields() {
+    let serde_json::Value::Object(result) =
+        serde_json::to_value(FlatLookupResult::default()).unwrap()
+    else {
+        panic!("FlatLookupResult is not an object");
+    };
+
+    let mut fields = db_export::field_names();
+    fields.sort();
+    assert_eq!(fields, result.keys().collect::<Vec<_>>());
+}
+
+#[test]
+fn test_parse_fields() {
+    assert_eq!(
+        db_export::parse_fields("country_code, asn,").unwrap(),
+        vec!["country_code", "asn"]
+    );
+    assert_eq!(
+        db_export::parse_fields("country_code,country")
+            .unwrap_err()
+            .to_string(),
+        "Unknown field \"country\""
+    );
+    assert_eq!(
+        db_export::parse_fields("city,asn, city")
+            .unwrap_err()
+            .to_string(),
+        "Duplicate field \"city\""
+    );
+}
+
+#[test]
+fn test_export_csv() {
+    let (count, output) = export(&["country_code", "city", "lat"], ExportFormat::Csv);
+    let output = String::from_utf8(output).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+
+    assert!(count > 0);
+    assert_eq!(lines.len() as u64, count + 1);
+    assert_eq!(lines[0], "network,country_code,city,lat");
+    assert!(lines.contains(&"89.160.20.128/25,SE,Linköping,58.4167"));
+    // Missing values are empty
+    assert!(lines.contains(&"2.3.3.0/24,,,"));
+}
+
+#[test]
+fn test_export_subdivision_codes_of_any_length() {
+    let (_, output) = export(&["country_code", "region_code"], ExportFormat::Csv);
+    let output = String::from_utf8(output).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+
+    assert!(lines.contains(&"89.160.20.128/25,SE,E"));
+    assert!(lines.contains(&"2.125.160.216/29,GB,ENG"));
+}
+
+#[test]
+fn test_export_jsonl() {
+    let (count, output) = export(&["country_code", "asn"], ExportFormat::Jsonl);
+    let output = String::from_utf8(output).unwrap();
+
+    assert_eq!(output.lines().count() as u64, count);
+
+    let line = output
+        .lines()
+        .find(|line| line.starts_with("{\"network\":\"89.160.20.128/25\""))
+        .unwrap();
+    let row: serde_json::Value = serde_json::from_str(line).unwrap();
+    assert_eq!(
+        row,
+        serde_json::json!({"network": "89.160.20.128/25", "country_code": "SE", "asn": null})
+    );
+}
+
+#[test]
+fn test_export_parquet() {
+    let (count, output) = export(
+        &["country_code", "lat", "accuracy_radius", "is_anycast"],
+        ExportFormat::Parquet,
+    );
+
+    let reader = SerializedFileReader::new(actix_web::web::Bytes::from(output)).unwrap();
+    assert_eq!(reader.metadata().file_metadata().num_rows() as u64, count);
+
+    let columns: Vec<String> = reader
+        .metadata()
+        .file_metadata()
+        .schema_descr()
+        .columns()
+        .iter()
+        .map(|column| column.name().to_string())
+        .collect();
+    assert_eq!(
+        columns,
+        vec![
+            "network",
+            "country_code",
+            "lat",
+            "accuracy_radius",
+            "is_anycast"
+        ]
+    );
+
+    let row = reader
+        .get_row_iter(None)
+        .unwrap()
+        .map(Result::unwrap)
+        .find(|row| row.get_string(0).unwrap() == "89.160.20.128/25")
+        .unwrap();
+    assert_eq!(row.get_string(1).unwrap(), "SE");
+    assert_eq!(row.get_double(2).unwrap(), 58.4167);
+    assert_eq!(row.get_long(3).unwrap(), 76);
+    assert!(row.get_bool(4).is_err());
+}
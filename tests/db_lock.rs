@@ -0,0 +1,88 @@
+use atlas_rs::db_lock::{UpdateLock, UsageLock};
+use atlas_rs::db_refresher::UpdatableDB;
+use atlas_rs::providers::DbProvider;
+use std::path::Path;
+use std::time::Duration;
+
+mod common;
+
+use common::{VARIANT, copy_test_db};
+
+/// Never used, the databases are already in place
+fn unused_provider() -> DbProvider {
+    DbProvider::Url {
+        url: "http://127.0.0.1:1/{VARIANT}.mmdb".to_string(),
+        token: None,
+    }
+}
+
+#[tokio::test]
+async fn test_update_lock_is_exclusive() {
+    let dir = tempfile::tempdir().unwrap();
+    let base_path = dir.path().to_str().unwrap().to_string();
+
+    let lock = UpdateLock::acquire(&base_path, VARIANT).await.unwrap();
+    assert!(UpdateLock::path(&base_path, VARIANT).exists());
+
+    let waiting = tokio::spawn(async move { UpdateLock::acquire(&base_path, VARIANT).await });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!waiting.is_finished());
+
+    drop(lock);
+    tokio::time::timeout(Duration::from_secs(5), waiting)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+}
+
+#[test]
+fn test_usage_lock() {
+    let dir = tempfile::tempdir().unwrap();
+    copy_test_db(dir.path());
+    let db_file = dir.path().join(format!("{VARIANT}.mmdb"));
+
+    assert!(!UsageLock::is_in_use(&db_file).unwrap());
+
+    let first = UsageLock::acquire(&db_file).unwrap();
+    let second = UsageLock::acquire(&db_file).unwrap();
+    assert!(UsageLock::is_in_use(&db_file).unwrap());
+
+    drop(first);
+    assert!(UsageLock::is_in_use(&db_file).unwrap());
+
+    drop(second);
+    assert!(!UsageLock::is_in_use(&db_file).unwrap());
+    assert!(!UsageLock::is_in_use(&dir.path().join("missing.mmdb")).unwrap());
+}
+
+#[tokio::test]
+async fn test_keeps_stale_database_in_use() {
+    let dir = tempfile::tempdir().unwrap();
+    let old_db_dir = dir.path().join(format!("{VARIANT}_20240101"));
+    let new_db_dir = dir.path().join(format!("{VARIANT}_20240201"));
+    copy_test_db(&old_db_dir);
+
+    let first = common::init_db(dir.path(), unused_provider()).await;
+    let second = common::init_db(dir.path(), unused_provider()).await;
+
+    // The first process downloaded a newer database while the second still uses the old one
+    copy_test_db(&new_db_dir);
+    first.update_db(0).await.unwrap();
+
+    assert_eq!(
+        Path::new(&first.db.read().await.base_path),
+        new_db_dir.as_path()
+    );
+    assert!(old_db_dir.exists());
+
+    // Once the second process switched to the new database, the old one is removed
+    second.update_db(0).await.unwrap();
+
+    assert_eq!(
+        Path::new(&second.db.read().await.base_path),
+        new_db_dir.as_path()
+    );
+    assert!(!old_db_dir.exists());
+    assert!(new_db_dir.exists());
+}
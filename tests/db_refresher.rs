@@ -0,0 +1,124 @@
+use atlas_rs::db_refresher::UpdateSchedule;
+use chrono::{DateTime, TimeZone, Utc};
+use std::time::Duration;
+
+/// Monday, January 1st 2024
+fn monday() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()
+}
+
+#[test]
+fn test_cron_schedule_next_delay() {
+    let schedule = UpdateSchedule::cron("0 6 * * TUE,FRI", Duration::ZERO).unwrap();
+    assert_eq!(
+        schedule.next_delay(monday()),
+        Duration::from_secs(30 * 60 * 60)
+    );
+
+    // Tuesday after the update waits until Friday
+    let tuesday = Utc.with_ymd_and_hms(2024, 1, 2, 6, 0, 0).unwrap();
+    assert_eq!(
+        schedule.next_delay(tuesday),
+        Duration::from_secs(3 * 24 * 60 * 60)
+    );
+}
+
+#[test]
+fn test_cron_schedule_numeric_days_of_week() {
+    let schedule = UpdateSchedule::cron("0 6 * * 2,5", Duration::ZERO).unwrap();
+    assert_eq!(
+        schedule.next_delay(monday()),
+        Duration::from_secs(30 * 60 * 60)
+    );
+
+    // Both 0 and 7 are Sunday
+    for sunday in ["0", "7"] {
+        let schedule = UpdateSchedule::cron(&format!("30 1 * * {sunday}"), Duration::ZERO).unwrap();
+        assert_eq!(
+            schedule.next_delay(monday()),
+            Duration::from_secs((6 * 24 + 1) * 60 * 60 + 30 * 60)
+        );
+    }
+
+    let schedule = UpdateSchedule::cron("0 6 * * 1-5", Duration::ZERO).unwrap();
+    assert_eq!(
+        schedule.next_delay(monday()),
+        Duration::from_secs(6 * 60 * 60)
+    );
+}
+
+#[test]
+fn test_cron_schedule_day_of_week_ranges_ending_on_sunday() {
+    // Friday to Sunday, after the Monday update
+    let schedule = UpdateSchedule::cron("0 6 * * 5-7", Duration::ZERO).unwrap();
+    assert_eq!(
+        schedule.next_delay(monday()),
+        Duration::from_secs((4 * 24 + 6) * 60 * 60)
+    );
+    let saturday = Utc.with_ymd_and_hms(2024, 1, 6, 6, 0, 0).unwrap();
+    assert_eq!(
+        schedule.next_delay(saturday),
+        Duration::from_secs(24 * 60 * 60)
+    );
+
+    let schedule = UpdateSchedule::cron("0 6 * * 1-7", Duration::ZERO).unwrap();
+    let sunday = Utc.with_ymd_and_hms(2024, 1, 7, 0, 0, 0).unwrap();
+    assert_eq!(
+        schedule.next_delay(sunday),
+        Duration::from_secs(6 * 60 * 60)
+    );
+
+    // Monday, Wednesday, Friday and Sunday
+    let schedule = UpdateSchedule::cron("0 6 * * 1-7/2", Duration::ZERO).unwrap();
+    assert_eq!(
+        schedule.next_delay(saturday),
+        Duration::from_secs(24 * 60 * 60)
+    );
+}
+
+#[test]
+fn test_cron_schedule_jitter() {
+    let schedule = UpdateSchedule::cron("0 6 * * TUE,FRI", Duration::from_secs(600)).unwrap();
+    let base = Duration::from_secs(30 * 60 * 60);
+
+    for _ in 0..100 {
+        let delay = schedule.next_delay(monday());
+        assert!(delay >= base && delay <= base + Duration::from_secs(600));
+    }
+}
+
+#[test]
+fn test_cron_schedule_retry_delay_is_capped_by_next_update() {
+    let schedule = UpdateSchedule::cron("0 6 * * *", Duration::ZERO).unwrap();
+    let before_update = Utc.with_ymd_and_hms(2024, 1, 1, 5, 59, 0).unwrap();
+
+    for failures in 1..10 {
+        assert!(schedule.retry_delay(failures, before_update) <= Duration::from_secs(60));
+    }
+}
+
+#[test]
+fn test_interval_schedule() {
+    let schedule = UpdateSchedule::Interval(Duration::from_secs(3600));
+    assert_eq!(schedule.next_delay(monday()), Duration::from_secs(3600));
+    assert!(schedule.retry_delay(1, monday()) <= Duration::from_secs(5 * 60));
+    assert!(schedule.retry_delay(20, monday()) <= Duration::from_secs(3600));
+}
+
+#[test]
+fn test_invalid_cron_expression() {
+    let error = UpdateSchedule::cron("0 6 * *", Duration::ZERO).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Invalid cron expression `0 6 * *`: expected 5 fields, got 4"
+    );
+
+    assert!(UpdateSchedule::cron("0 25 * * *", Duration::ZERO).is_err());
+    assert!(UpdateSchedule::cron("0 6 * * FOO", Duration::ZERO).is_err());
+
+    let error = UpdateSchedule::cron("0 0 30 2 *", Duration::from_secs(60)).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Invalid cron expression `0 0 30 2 *`: never matches"
+    );
+}
@@ -0,0 +1,61 @@
+use actix_web::web::Data;
+use atlas_rs::maxmind_db::{DbLayout, MaxmindDB};
+use atlas_rs::providers::DbProvider;
+use std::path::Path;
+use std::time::Duration;
+
+const TEST_DB: &str = "tests-data/GeoIP2-City-Test_1/GeoIP2-City-Test.mmdb";
+const VARIANT: &str = "GeoIP2-City-Test";
+
+async fn wait_for_base_path(db: &MaxmindDB, expected: &Path) -> bool {
+    for _ in 0..100 {
+        if Path::new(&db.db.read().await.base_path) == expected {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    false
+}
+
+#[tokio::test]
+async fn test_reloads_new_versioned_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let initial_dir = dir.path().join(format!("{VARIANT}_20240101"));
+    std::fs::create_dir(&initial_dir).unwrap();
+    std::fs::copy(TEST_DB, initial_dir.join(format!("{VARIANT}.mmdb"))).unwrap();
+
+    let db = Data::new(
+        MaxmindDB::init_with_provider(
+            VARIANT,
+            dir.path().to_str().unwrap(),
+            DbProvider::Local,
+            DbLayout::Versioned,
+        )
+        .await
+        .unwrap(),
+    );
+    tokio::spawn(atlas_rs::start_db_watcher(
+        db.clone(),
+        Duration::from_millis(100),
+    ));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // An invalid database is not loaded
+    let broken_dir = dir.path().join(format!("{VARIANT}_20240102"));
+    std::fs::create_dir(&broken_dir).unwrap();
+    std::fs::write(
+        broken_dir.join(format!("{VARIANT}.mmdb")),
+        b"not a database",
+    )
+    .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(Path::new(&db.db.read().await.base_path), initial_dir);
+
+    let new_dir = dir.path().join(format!("{VARIANT}_20240103"));
+    std::fs::create_dir(&new_dir).unwrap();
+    std::fs::copy(TEST_DB, new_dir.join(format!("{VARIANT}.mmdb"))).unwrap();
+
+    assert!(wait_for_base_path(&db, &new_dir).await);
+}
@@ -0,0 +1,277 @@
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, web};
+use atlas_rs::db_lock::UsageLock;
+use atlas_rs::download_utils::{
+    DownloadState, RetryPolicy, backoff_with_jitter, download_file, remove_orphaned_downloads,
+};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const BODY: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+fn retry_policy() -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(50),
+    }
+}
+
+/// Starts a server on `/file` with the given handler and returns the URL
+fn start_server<F, Fut>(handler: F) -> String
+where
+    F: Fn(HttpRequest) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = HttpResponse> + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = HttpServer::new(move || App::new().route("/file", web::get().to(handler.clone())))
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+
+    tokio::spawn(server);
+
+    format!("http://127.0.0.1:{port}/file")
+}
+
+#[actix_web::test]
+async fn test_retries_server_errors() {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let server_requests = requests.clone();
+
+    let url = start_server(move |_| {
+        let requests = server_requests.clone();
+        async move {
+            match requests.fetch_add(1, Ordering::SeqCst) {
+                0 => HttpResponse::ServiceUnavailable().finish(),
+                _ => HttpResponse::Ok().body(BODY),
+            }
+        }
+    });
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.part");
+
+    download_file(
+        &reqwest::Client::new(),
+        &url,
+        &path,
+        &DownloadState::default(),
+        &retry_policy(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(path).unwrap(), BODY);
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+}
+
+#[actix_web::test]
+async fn test_gives_up_after_max_attempts() {
+    let url = start_server(|_| async { HttpResponse::InternalServerError().finish() });
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.part");
+
+    let result = download_file(
+        &reqwest::Client::new(),
+        &url,
+        &path,
+        &DownloadState::default(),
+        &retry_policy(),
+    )
+    .await;
+
+    assert!(result.is_err());
+    assert!(!path.exists());
+}
+
+/// Starts a raw HTTP server which breaks the connection after `cut_at` bytes of the body of full
+/// responses and serves ranges of the body, or fails range requests unless `serve_ranges`.
+/// Returns the URL and the received requests, their range or `full`.
+async fn start_interrupting_server(
+    cut_at: usize,
+    serve_ranges: bool,
+) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let server_requests = requests.clone();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let read = stream.read(&mut request).await.unwrap();
+            let request = String::from_utf8_lossy(&request[..read]).to_lowercase();
+
+            let range = request
+                .lines()
+                .find_map(|line| line.strip_prefix("range: bytes="))
+                .map(|range| range.trim_end_matches('-').parse::<usize>().unwrap());
+
+            server_requests.lock().unwrap().push(match range {
+                Some(start) => format!("bytes={start}-"),
+                None => "full".to_string(),
+            });
+
+            let response = match range {
+                Some(_) if !serve_ranges => {
+                    b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n".to_vec()
+                }
+                Some(start) => {
+                    let mut response = format!(
+                        "HTTP/1.1 206 Partial Content\r\nETag: \"v1\"\r\nContent-Length: {}\r\n\r\n",
+                        BODY.len() - start
+                    )
+                    .into_bytes();
+                    response.extend_from_slice(&BODY[start..]);
+                    response
+                }
+                None => {
+                    let mut response = format!(
+                        "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: {}\r\n\r\n",
+                        BODY.len()
+                    )
+                    .into_bytes();
+                    response.extend_from_slice(&BODY[..cut_at]);
+                    response
+                }
+            };
+
+            stream.write_all(&response).await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+    });
+
+    (format!("http://127.0.0.1:{port}/file"), requests)
+}
+
+#[tokio::test]
+async fn test_resumes_interrupted_download() {
+    let (url, requests) = start_interrupting_server(10, true).await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.part");
+
+    download_file(
+        &reqwest::Client::new(),
+        &url,
+        &path,
+        &DownloadState::default(),
+        &retry_policy(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(path).unwrap(), BODY);
+    assert_eq!(*requests.lock().unwrap(), vec!["full", "bytes=10-"]);
+}
+
+#[tokio::test]
+async fn test_resumes_partial_download_of_earlier_attempt() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.part");
+
+    let (url, _) = start_interrupting_server(20, false).await;
+    let result = download_file(
+        &reqwest::Client::new(),
+        &url,
+        &path,
+        &DownloadState::default(),
+        &retry_policy(),
+    )
+    .await;
+    assert!(result.is_err());
+    assert_eq!(std::fs::read(&path).unwrap(), &BODY[..20]);
+
+    // The same file, resumed from where the earlier attempt stopped without fetching it again
+    let (url, requests) = start_interrupting_server(10, true).await;
+    download_file(
+        &reqwest::Client::new(),
+        &url,
+        &path,
+        &DownloadState::default(),
+        &retry_policy(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), BODY);
+    assert_eq!(*requests.lock().unwrap(), vec!["bytes=20-"]);
+    assert!(!dir.path().join("file.part.json").exists());
+}
+
+#[tokio::test]
+async fn test_remove_orphaned_downloads() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("GeoLite2-City_20240101.tar.gz"), b"").unwrap();
+    std::fs::write(dir.path().join("GeoLite2-City_20240102.tar.gz.part"), b"").unwrap();
+    // Resumable with the validators of its file
+    std::fs::write(dir.path().join("GeoLite2-City_20240104.tar.gz.part"), b"").unwrap();
+    std::fs::write(
+        dir.path().join("GeoLite2-City_20240104.tar.gz.part.json"),
+        b"{}",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("GeoLite2-City_20240105.tar.gz.part.json"),
+        b"{}",
+    )
+    .unwrap();
+    std::fs::create_dir(dir.path().join("GeoLite2-City_20231231")).unwrap();
+    std::fs::create_dir(dir.path().join(".GeoLite2-City_20240103.tar.gz.tmp")).unwrap();
+    std::fs::write(dir.path().join(".GeoLite2-City.download.json.tmp"), b"").unwrap();
+    // Set aside databases are kept while in use
+    let set_aside = dir.path().join(".GeoLite2-City_20240106.unloadable.tmp");
+    std::fs::create_dir(&set_aside).unwrap();
+    std::fs::write(set_aside.join("GeoLite2-City.mmdb"), b"").unwrap();
+    let _usage = UsageLock::acquire(&set_aside.join("GeoLite2-City.mmdb")).unwrap();
+    let unused_aside = dir.path().join(".GeoLite2-City_20240107.unloadable.tmp");
+    std::fs::create_dir(&unused_aside).unwrap();
+    std::fs::write(unused_aside.join("GeoLite2-City.mmdb"), b"").unwrap();
+    // Downloads of other editions may be in progress
+    std::fs::write(dir.path().join("GeoLite2-ASN_20240102.tar.gz.part"), b"").unwrap();
+    std::fs::write(dir.path().join("GeoLite2-City-ISP_20240102.tar.gz"), b"").unwrap();
+    std::fs::create_dir(dir.path().join(".GeoLite2-ASN_20240103.tar.gz.tmp")).unwrap();
+
+    remove_orphaned_downloads(dir.path().to_str().unwrap(), "GeoLite2-City")
+        .await
+        .unwrap();
+
+    let mut files: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        vec![
+            ".GeoLite2-ASN_20240103.tar.gz.tmp",
+            ".GeoLite2-City_20240106.unloadable.tmp",
+            "GeoLite2-ASN_20240102.tar.gz.part",
+            "GeoLite2-City-ISP_20240102.tar.gz",
+            "GeoLite2-City_20231231",
+            "GeoLite2-City_20240104.tar.gz.part",
+            "GeoLite2-City_20240104.tar.gz.part.json",
+        ]
+    );
+}
+
+#[test]
+fn test_backoff_with_jitter() {
+    let initial = Duration::from_secs(1);
+    let max = Duration::from_secs(10);
+
+    for (attempt, backoff) in [(1, 1), (2, 2), (3, 4), (4, 8), (5, 10), (50, 10)] {
+        let backoff = Duration::from_secs(backoff);
+        let jittered = backoff_with_jitter(initial, max, attempt);
+
+        assert!(jittered >= backoff / 2 && jittered <= backoff);
+    }
+}
@@ -0,0 +1,380 @@
+use atlas_rs::auth::ApiKeyStore;
+use atlas_rs::grpc::GrpcConfig;
+use atlas_rs::grpc::proto::geo_ip_client::GeoIpClient;
+use atlas_rs::grpc::proto::{GetMetadataRequest, LookupRequest, LookupType};
+use atlas_rs::rate_limit::RateLimiter;
+use atlas_rs::tls::TlsConfig;
+use prost_types::value::Kind;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig};
+
+mod common;
+
+use common::load_test_db;
+
+const TLS_DIR: &str = "tests-data/tls";
+
+/// Starts a gRPC server with `config` and returns its address
+async fn start_server(config: GrpcConfig) -> SocketAddr {
+    let app_data = load_test_db().await;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(atlas_rs::grpc::serve(app_data, listener, config));
+
+    addr
+}
+
+async fn setup_with(config: GrpcConfig) -> GeoIpClient<Channel> {
+    let addr = start_server(config).await;
+
+    GeoIpClient::connect(format!("http://{addr}"))
+        .await
+        .unwrap()
+}
+
+async fn setup() -> GeoIpClient<Channel> {
+    setup_with(GrpcConfig::default()).await
+}
+
+fn lookup_request(ip_addresses: &[&str]) -> LookupRequest {
+    LookupRequest {
+        lookup_type: LookupType::City.into(),
+        ip_addresses: ip_addresses.iter().map(ToString::to_string).collect(),
+        flat: false,
+    }
+}
+
+fn string_field(record: &prost_types::Struct, path: &[&str]) -> Option<String> {
+    let (last, path) = path.split_last()?;
+    let mut record = record;
+
+    for key in path {
+        match record.fields.get(*key)?.kind.as_ref()? {
+            Kind::StructValue(inner) => record = inner,
+            _ => return None,
+        }
+    }
+
+    match record.fields.get(*last)?.kind.as_ref()? {
+        Kind::StringValue(value) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn test_lookup() {
+    let mut client = setup().await;
+
+    let resp = client
+        .lookup(LookupRequest {
+            lookup_type: LookupType::City.into(),
+            ip_addresses: vec!["214.78.120.1".to_string(), "1.1.1.1".to_string()],
+            flat: false,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+
+    assert_eq!(resp.results.len(), 2);
+    assert_eq!(resp.results[0].ip_address, "214.78.120.1");
+
+    let record = resp.results[0].record.as_ref().unwrap();
+    assert_eq!(
+        string_field(record, &["city", "names", "en"]).as_deref(),
+        Some("San Diego")
+    );
+
+    assert_eq!(resp.results[1].ip_address, "1.1.1.1");
+    assert!(resp.results[1].record.is_none());
+}
+
+#[tokio::test]
+async fn test_raw_lookup() {
+    let mut client = setup().await;
+
+    let resp = client
+        .lookup(LookupRequest {
+            lookup_type: LookupType::Raw.into(),
+            ip_addresses: vec!["214.78.120.1".to_string()],
+            flat: false,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+
+    let record = resp.results[0].record.as_ref().unwrap();
+    assert_eq!(
+        string_field(record, &["country", "iso_code"]).as_deref(),
+        Some("US")
+    );
+}
+
+#[tokio::test]
+async fn test_lookup_rejects_invalid_ip() {
+    let mut client = setup().await;
+
+    let status = client
+        .lookup(LookupRequest {
+            lookup_type: LookupType::City.into(),
+            ip_addresses: vec!["192.168.1.".to_string()],
+            flat: false,
+        })
+        .await
+        .unwrap_err();
+
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn test_lookup_stream() {
+    let mut client = setup().await;
+
+    let requests = tokio_stream::iter(vec![
+        LookupRequest {
+            lookup_type: LookupType::Country.into(),
+            ip_addresses: vec!["214.78.120.1".to_string()],
+            flat: true,
+        },
+        LookupRequest {
+            lookup_type: LookupType::City.into(),
+            ip_addresses: vec!["214.78.120.2".to_string()],
+            flat: true,
+        },
+    ]);
+
+    let mut responses = client.lookup_stream(requests).await.unwrap().into_inner();
+
+    let first = responses.message().await.unwrap().unwrap();
+    let record = first.results[0].record.as_ref().unwrap();
+    assert_eq!(
+        string_field(record, &["country_code"]).as_deref(),
+        Some("US")
+    );
+
+    let second = responses.message().await.unwrap().unwrap();
+    assert_eq!(second.results[0].ip_address, "214.78.120.2");
+
+    assert!(responses.message().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_lookup_stream_continues_after_invalid_request() {
+    let mut client = setup().await;
+
+    let requests = tokio_stream::iter(vec![
+        lookup_request(&["not-an-ip"]),
+        lookup_request(&["214.78.120.1"]),
+    ]);
+
+    let mut responses = client.lookup_stream(requests).await.unwrap().into_inner();
+
+    let first = responses.message().await.unwrap().unwrap();
+    let error = first.error.unwrap();
+    assert_eq!(error.code, i32::from(tonic::Code::InvalidArgument));
+    assert_eq!(error.message, "Invalid IP Address \"not-an-ip\"");
+    assert!(first.results.is_empty());
+
+    let second = responses.message().await.unwrap().unwrap();
+    assert!(second.error.is_none());
+    assert_eq!(second.results[0].ip_address, "214.78.120.1");
+    assert!(second.results[0].record.is_some());
+
+    assert!(responses.message().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_get_metadata() {
+    let mut client = setup().await;
+
+    let resp = client
+        .get_metadata(GetMetadataRequest {})
+        .await
+        .unwrap()
+        .into_inner();
+
+    assert_eq!(resp.variant, "GeoIP2-City-Test");
+    assert_eq!(resp.database_type, "GeoIP2-City");
+    assert!(resp.build_epoch > 0);
+}
+
+#[tokio::test]
+async fn test_lookup_repeated_ip() {
+    let mut client = setup().await;
+
+    let resp = client
+        .lookup(lookup_request(&["214.78.120.1", "214.78.120.1"]))
+        .await
+        .unwrap()
+        .into_inner();
+
+    assert_eq!(resp.results.len(), 2);
+    for result in &resp.results {
+        let record = result.record.as_ref().unwrap();
+        assert_eq!(
+            string_field(record, &["city", "names", "en"]).as_deref(),
+            Some("San Diego")
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_requires_api_key() {
+    let api_keys = ApiKeyStore::load(Some("secret"), None).await.unwrap();
+    let mut client = setup_with(GrpcConfig {
+        api_keys: api_keys.map(actix_web::web::Data::new),
+        ..GrpcConfig::default()
+    })
+    .await;
+
+    let status = client
+        .lookup(lookup_request(&["214.78.120.1"]))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+    let mut request = tonic::Request::new(lookup_request(&["214.78.120.1"]));
+    request
+        .metadata_mut()
+        .insert("x-api-key", "wrong".parse().unwrap());
+    let status = client.lookup(request).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+    let mut request = tonic::Request::new(GetMetadataRequest {});
+    request
+        .metadata_mut()
+        .insert("x-api-key", "secret".parse().unwrap());
+    assert!(client.get_metadata(request).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_limits_api_key_requests() {
+    let api_keys = ApiKeyStore::load(Some("secret:1"), None).await.unwrap();
+    let mut client = setup_with(GrpcConfig {
+        api_keys: api_keys.map(actix_web::web::Data::new),
+        ..GrpcConfig::default()
+    })
+    .await;
+
+    let request = || {
+        let mut request = tonic::Request::new(GetMetadataRequest {});
+        request
+            .metadata_mut()
+            .insert("x-api-key", "secret".parse().unwrap());
+        request
+    };
+
+    assert!(client.get_metadata(request()).await.is_ok());
+
+    let status = client.get_metadata(request()).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    assert!(status.metadata().get("retry-after").is_some());
+}
+
+#[tokio::test]
+async fn test_limits_api_key_streams() {
+    let api_keys = ApiKeyStore::load(Some("secret::2"), None).await.unwrap();
+    let mut client = setup_with(GrpcConfig {
+        api_keys: api_keys.map(actix_web::web::Data::new),
+        ..GrpcConfig::default()
+    })
+    .await;
+
+    let requests = tokio_stream::iter(vec![
+        lookup_request(&["214.78.120.1"]),
+        lookup_request(&["214.78.120.1"]),
+        lookup_request(&["214.78.120.1"]),
+    ]);
+    let mut request = tonic::Request::new(requests);
+    request
+        .metadata_mut()
+        .insert("x-api-key", "secret".parse().unwrap());
+
+    // Each message counts towards the daily quota
+    let mut responses = client.lookup_stream(request).await.unwrap().into_inner();
+    assert!(responses.message().await.unwrap().unwrap().error.is_none());
+    assert!(responses.message().await.unwrap().unwrap().error.is_none());
+    let error = responses.message().await.unwrap().unwrap().error.unwrap();
+    assert_eq!(error.code, i32::from(tonic::Code::ResourceExhausted));
+    assert_eq!(error.message, "Daily quota exceeded for API key");
+    assert!(error.retry_after.is_some());
+}
+
+#[tokio::test]
+async fn test_limits_lookups() {
+    let rate_limiter = RateLimiter::new(Some(1), Some(2));
+    let mut client = setup_with(GrpcConfig {
+        rate_limiter: rate_limiter.map(actix_web::web::Data::new),
+        ..GrpcConfig::default()
+    })
+    .await;
+
+    assert!(
+        client
+            .lookup(lookup_request(&["214.78.120.1"]))
+            .await
+            .is_ok()
+    );
+    let status = client
+        .lookup(lookup_request(&["214.78.120.1"]))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+
+    // Batches are limited by their number of IP Addresses
+    assert!(
+        client
+            .lookup(lookup_request(&["214.78.120.1", "1.1.1.1"]))
+            .await
+            .is_ok()
+    );
+
+    // Messages of lookup streams are limited too
+    let requests = tokio_stream::iter(vec![lookup_request(&["214.78.120.1", "1.1.1.1"])]);
+    let mut responses = client.lookup_stream(requests).await.unwrap().into_inner();
+    let error = responses.message().await.unwrap().unwrap().error.unwrap();
+    assert_eq!(error.code, i32::from(tonic::Code::ResourceExhausted));
+}
+
+#[tokio::test]
+async fn test_serves_tls() {
+    let addr = start_server(GrpcConfig {
+        tls: Some(TlsConfig {
+            cert_path: Path::new(TLS_DIR).join("server.pem"),
+            key_path: Path::new(TLS_DIR).join("server-key.pem"),
+            client_ca_path: None,
+            reload_interval: Duration::from_secs(60),
+        }),
+        ..GrpcConfig::default()
+    })
+    .await;
+
+    let ca = std::fs::read(Path::new(TLS_DIR).join("ca.pem")).unwrap();
+    let channel = Channel::from_shared(format!("https://localhost:{}", addr.port()))
+        .unwrap()
+        .tls_config(
+            ClientTlsConfig::new()
+                .ca_certificate(Certificate::from_pem(ca))
+                .domain_name("localhost"),
+        )
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+
+    let resp = GeoIpClient::new(channel)
+        .get_metadata(GetMetadataRequest {})
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(resp.variant, "GeoIP2-City-Test");
+
+    // Plaintext clients are refused
+    let mut client = GeoIpClient::connect(format!("http://{addr}"))
+        .await
+        .unwrap();
+    assert!(client.get_metadata(GetMetadataRequest {}).await.is_err());
+}
@@ -0,0 +1,80 @@
+use atlas_rs::maxmind_db::{DbLayout, MaxmindDB};
+use atlas_rs::providers::DbProvider;
+use std::time::{Duration, SystemTime};
+
+mod common;
+
+use common::{VARIANT, copy_test_db};
+
+#[tokio::test]
+async fn test_fails_without_database() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let result = MaxmindDB::init_with_provider(
+        VARIANT,
+        dir.path().to_str().unwrap(),
+        DbProvider::Local,
+        DbLayout::Versioned,
+    )
+    .await;
+
+    assert!(result.is_err());
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+#[tokio::test]
+async fn test_loads_flat_database() {
+    let dir = tempfile::tempdir().unwrap();
+    copy_test_db(dir.path());
+
+    let db = MaxmindDB::init_with_provider(
+        VARIANT,
+        dir.path().to_str().unwrap(),
+        DbProvider::Local,
+        DbLayout::Versioned,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        db.db.read().await.base_path,
+        dir.path().to_str().unwrap().to_string()
+    );
+    assert!(!db.reload_from_disk().await.unwrap());
+}
+
+#[tokio::test]
+async fn test_reloads_changed_database() {
+    let dir = tempfile::tempdir().unwrap();
+    copy_test_db(dir.path());
+
+    let db = MaxmindDB::init_with_provider(
+        VARIANT,
+        dir.path().to_str().unwrap(),
+        DbProvider::Local,
+        DbLayout::Versioned,
+    )
+    .await
+    .unwrap();
+
+    // A replaced file is detected by its modification time
+    std::fs::File::options()
+        .write(true)
+        .open(dir.path().join(format!("{VARIANT}.mmdb")))
+        .unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(60))
+        .unwrap();
+
+    assert!(db.reload_from_disk().await.unwrap());
+    assert!(!db.reload_from_disk().await.unwrap());
+
+    // Versioned directories take precedence
+    let versioned_dir = dir.path().join(format!("{VARIANT}_20240101"));
+    copy_test_db(&versioned_dir);
+
+    assert!(db.reload_from_disk().await.unwrap());
+    assert_eq!(
+        db.db.read().await.base_path,
+        versioned_dir.to_str().unwrap().to_string()
+    );
+}
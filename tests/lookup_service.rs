@@ -3,6 +3,10 @@ use actix_web::dev::{Service, ServiceResponse};
 use actix_web::{App, test, web::Data};
 use atlas_rs::maxmind_db::MaxmindDB;
 
+mod common;
+
+use common::load_test_db;
+
 struct SetupResult<T> {
     service: T,
     app_data: Data<MaxmindDB>,
@@ -15,9 +19,7 @@ async fn setup() -> SetupResult<
         Response = ServiceResponse<impl MessageBody>,
     >,
 > {
-    let app_data = atlas_rs::init_db("tests-data/", "GeoIP2-City-Test")
-        .await
-        .unwrap();
+    let app_data = load_test_db().await;
     let service = test::init_service(
         App::new()
             .app_data(app_data.clone())
@@ -123,3 +125,149 @@ async fn test_rejects_too_many_ips() {
         "TOO_MANY_IPS".to_string()
     );
 }
+
+#[actix_web::test]
+async fn test_flat_format() {
+    let setup = setup().await;
+    let req = test::TestRequest::get()
+        .uri("/geoip/lookup/city/214.78.120.1?format=flat")
+        .to_request();
+
+    let resp: serde_json::Value = test::call_and_read_body_json(&setup.service, req).await;
+    let result = &resp["results"]["214.78.120.1"];
+
+    assert_eq!(result["country_code"], "US");
+    assert_eq!(result["city"], "San Diego");
+    assert_eq!(result["region_code"], "CA");
+    assert!(result["asn"].is_null());
+}
+
+#[actix_web::test]
+async fn test_invalid_format() {
+    let setup = setup().await;
+    let req = test::TestRequest::get()
+        .uri("/geoip/lookup/city/214.78.120.1?format=nested")
+        .to_request();
+
+    let resp: serde_json::Value = test::call_and_read_body_json(&setup.service, req).await;
+
+    assert_eq!(
+        resp["error"]["code"].as_str().unwrap(),
+        "INVALID_FORMAT".to_string()
+    );
+}
+
+#[actix_web::test]
+async fn test_csv_format() {
+    let setup = setup().await;
+    let req = test::TestRequest::get()
+        .uri("/geoip/lookup/city/214.78.120.1,1.1.1.1?format=csv")
+        .to_request();
+
+    let resp = test::call_service(&setup.service, req).await;
+    assert_eq!(resp.headers().get("content-type").unwrap(), "text/csv");
+
+    let body = test::read_body(resp).await;
+    let body = std::str::from_utf8(&body).unwrap();
+    let mut lines = body.lines();
+
+    assert!(
+        lines
+            .next()
+            .unwrap()
+            .starts_with("ip,continent_code,country_code,")
+    );
+    assert!(lines.next().unwrap().starts_with("1.1.1.1,,,"));
+    assert!(lines.next().unwrap().starts_with("214.78.120.1,NA,US,"));
+    assert!(lines.next().is_none());
+}
+
+#[actix_web::test]
+async fn test_ndjson_format() {
+    let setup = setup().await;
+    let req = test::TestRequest::get()
+        .uri("/geoip/lookup/country/214.78.120.1,214.78.120.2?format=ndjson")
+        .to_request();
+
+    let body = test::call_and_read_body(&setup.service, req).await;
+    let lines: Vec<serde_json::Value> = std::str::from_utf8(&body)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    assert_eq!(lines.len(), 2);
+    for line in lines {
+        assert!(line["ip"].as_str().unwrap().starts_with("214.78.120."));
+        assert_eq!(line["result"]["country"]["iso_code"], "US");
+    }
+}
+
+#[actix_web::test]
+async fn test_msgpack_accept_header() {
+    let setup = setup().await;
+    let req = test::TestRequest::get()
+        .uri("/geoip/lookup/city/214.78.120.1")
+        .insert_header(("Accept", "application/msgpack"))
+        .to_request();
+
+    let resp = test::call_service(&setup.service, req).await;
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/msgpack"
+    );
+
+    let body = test::read_body(resp).await;
+    let resp: serde_json::Value = rmp_serde::from_slice(&body).unwrap();
+
+    assert_eq!(
+        resp["results"]["214.78.120.1"]["city"]["names"]["en"],
+        "San Diego"
+    );
+}
+
+#[actix_web::test]
+async fn test_cbor_format() {
+    let setup = setup().await;
+    let req = test::TestRequest::get()
+        .uri("/geoip/lookup/city/214.78.120.1?format=cbor")
+        .to_request();
+
+    let body = test::call_and_read_body(&setup.service, req).await;
+    let resp: serde_json::Value = ciborium::from_reader(body.as_ref()).unwrap();
+
+    assert_eq!(
+        resp["results"]["214.78.120.1"]["city"]["names"]["en"],
+        "San Diego"
+    );
+}
+
+#[actix_web::test]
+async fn test_raw_lookup() {
+    let setup = setup().await;
+    let req = test::TestRequest::get()
+        .uri("/geoip/lookup/raw/214.78.120.1")
+        .to_request();
+
+    let resp: serde_json::Value = test::call_and_read_body_json(&setup.service, req).await;
+    let result = &resp["results"]["214.78.120.1"];
+
+    assert_eq!(result["city"]["geoname_id"], 5391811);
+    assert_eq!(result["city"]["names"]["en"], "San Diego");
+    assert_eq!(result["country"]["iso_code"], "US");
+}
+
+#[actix_web::test]
+async fn test_raw_lookup_flat_format() {
+    let setup = setup().await;
+    let req = test::TestRequest::get()
+        .uri("/geoip/lookup/raw/214.78.120.1?format=flat")
+        .to_request();
+
+    let resp: serde_json::Value = test::call_and_read_body_json(&setup.service, req).await;
+    let result = &resp["results"]["214.78.120.1"];
+
+    assert_eq!(result["country_code"], "US");
+    assert_eq!(result["city"], "San Diego");
+    assert_eq!(result["region_code"], "CA");
+}
@@ -0,0 +1,128 @@
+use actix_web::{App, HttpServer, web};
+use atlas_rs::download_utils::AlreadyDownloaded;
+use atlas_rs::maxmind_db::{DbLayout, MaxmindDB};
+use atlas_rs::mirror::MirrorConfig;
+use atlas_rs::providers::{DbProvider, Downloader};
+use std::net::TcpListener;
+
+mod common;
+
+use common::load_test_db;
+
+const VARIANT: &str = "GeoIP2-City-Test";
+const TOKEN: &str = "mirror-secret";
+
+/// Starts an instance serving the test database on the mirror endpoint and returns its URL
+async fn start_mirror() -> String {
+    let data = load_test_db().await;
+    let mirror = web::Data::new(MirrorConfig::new(TOKEN.to_string()));
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(data.clone())
+            .app_data(mirror.clone())
+            .service(atlas_rs::services::mirror::handle)
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+
+    tokio::spawn(server);
+
+    format!("http://127.0.0.1:{port}")
+}
+
+#[actix_web::test]
+async fn test_mirror_requires_token() {
+    let url = start_mirror().await;
+    let client = reqwest::Client::new();
+
+    let resp = client
+        .get(format!("{url}/mirror/{VARIANT}"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
+
+    let resp = client
+        .get(format!("{url}/mirror/{VARIANT}"))
+        .bearer_auth("wrong")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
+
+    let resp = client
+        .head(format!("{url}/mirror/{VARIANT}"))
+        .bearer_auth(TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let resp = client
+        .get(format!("{url}/mirror/GeoIP2-Country"))
+        .basic_auth("atlas", Some(TOKEN))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 404);
+}
+
+#[actix_web::test]
+async fn test_downloads_database_from_mirror() {
+    let url = start_mirror().await;
+    let dir = tempfile::tempdir().unwrap();
+    let output_path = dir.path().to_str().unwrap();
+
+    let provider = DbProvider::Mirror {
+        url,
+        token: Some(TOKEN.to_string()),
+    };
+    let db =
+        MaxmindDB::init_with_provider(VARIANT, output_path, provider.clone(), DbLayout::Versioned)
+            .await
+            .unwrap();
+
+    let upstream_db = load_test_db().await;
+    let db = db.db.read().await;
+    assert_eq!(db.build_epoch(), upstream_db.db.read().await.build_epoch());
+    assert!(
+        db.base_path
+            .starts_with(&format!("{output_path}/{VARIANT}_"))
+    );
+
+    // The mirror responds with `304 Not Modified` to the next download
+    let error = provider
+        .fetch_latest_db(
+            &Downloader::from_env(&provider).unwrap(),
+            VARIANT,
+            output_path,
+        )
+        .await
+        .unwrap_err();
+    assert!(error.downcast_ref::<AlreadyDownloaded>().is_some());
+}
+
+#[actix_web::test]
+async fn test_mirror_provider_requires_token() {
+    let provider = DbProvider::Mirror {
+        url: "http://127.0.0.1:1".to_string(),
+        token: None,
+    };
+    let dir = tempfile::tempdir().unwrap();
+
+    let error = provider
+        .fetch_latest_db(
+            &Downloader::from_env(&provider).unwrap(),
+            VARIANT,
+            dir.path().to_str().unwrap(),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(error.to_string(), "DB_DOWNLOAD_TOKEN env var not set");
+}
@@ -0,0 +1,250 @@
+use actix_web::{App, test as actix_test, web};
+use atlas_rs::overlay::{self, Overlay, OverlayMode};
+use atlas_rs::providers::DbProvider;
+use serde_json::json;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+mod common;
+
+use common::init_db;
+
+const CSV_OVERLAY: &str = "\
+network,mode,country.iso_code,country.names.en,traits.autonomous_system_number,postal.code
+214.78.120.0/24,,DE,Germany,64512,
+214.78.120.2/32,replace,FR,,,92101
+";
+
+const YAML_OVERLAY: &str = "
+- network: 214.78.120.0/24
+  fields:
+    country:
+      iso_code: DE
+      names.en: Germany
+    subdivisions.0.iso_code: BE
+- network: 4.2.2.4
+  mode: replace
+  fields:
+    country: {iso_code: US}
+";
+
+#[test]
+fn test_parse_csv_entries() {
+    let entries = overlay::parse_entries(Path::new("overlay.csv"), CSV_OVERLAY).unwrap();
+
+    assert_eq!(entries.len(), 2);
+    // Sorted from the least to the most specific network
+    assert_eq!(entries[0].network.to_string(), "214.78.120.0/24");
+    assert_eq!(entries[0].mode, OverlayMode::Merge);
+    assert_eq!(
+        entries[0].apply(Some(json!({"city": {"names": {"en": "San Diego"}}}))),
+        json!({
+            "city": {"names": {"en": "San Diego"}},
+            "country": {"iso_code": "DE", "names": {"en": "Germany"}},
+            "traits": {"autonomous_system_number": 64512},
+        })
+    );
+    assert_eq!(entries[1].mode, OverlayMode::Replace);
+    assert_eq!(
+        entries[1].apply(Some(json!({"city": {"names": {"en": "San Diego"}}}))),
+        json!({"country": {"iso_code": "FR"}, "postal": {"code": "92101"}})
+    );
+
+    // Only cells of numeric and flag fields are typed
+    let entries = overlay::parse_entries(
+        Path::new("overlay.csv"),
+        "network,city.names.en,location.latitude,traits.is_anycast\n1.2.3.0/24,null,52.5,true\n",
+    )
+    .unwrap();
+    assert_eq!(
+        entries[0].apply(None),
+        json!({
+            "city": {"names": {"en": "null"}},
+            "location": {"latitude": 52.5},
+            "traits": {"is_anycast": true},
+        })
+    );
+
+    let error = overlay::parse_entries(
+        Path::new("overlay.csv"),
+        "network,traits.autonomous_system_number\n1.2.3.0/24,AS64512\n",
+    )
+    .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Invalid value \"AS64512\" of traits.autonomous_system_number"
+    );
+
+    let error =
+        overlay::parse_entries(Path::new("overlay.csv"), "network\n300.0.0.0/8\n").unwrap_err();
+    assert_eq!(error.to_string(), "Invalid network \"300.0.0.0/8\"");
+}
+
+#[test]
+fn test_parse_yaml_entries() {
+    let entries = overlay::parse_entries(Path::new("overlay.yaml"), YAML_OVERLAY).unwrap();
+
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[1].network.to_string(), "4.2.2.4/32");
+    assert_eq!(
+        entries[0].apply(Some(
+            json!({"subdivisions": [{"iso_code": "CA"}, {"iso_code": "X"}]})
+        )),
+        json!({
+            "country": {"iso_code": "DE", "names": {"en": "Germany"}},
+            "subdivisions": [{"iso_code": "BE"}, {"iso_code": "X"}],
+        })
+    );
+    assert_eq!(
+        entries[1].apply(None),
+        json!({"country": {"iso_code": "US"}})
+    );
+}
+
+#[actix_web::test]
+async fn test_lookup_with_overlay() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("overlay.csv");
+    std::fs::write(&path, CSV_OVERLAY).unwrap();
+
+    let db = init_db(Path::new("tests-data/"), DbProvider::Local)
+        .await
+        .with_overlay(Some(Arc::new(Overlay::load(&path).unwrap())));
+    let service = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(db))
+            .service(atlas_rs::services::lookup::handle),
+    )
+    .await;
+
+    let req = actix_test::TestRequest::get()
+        .uri("/geoip/lookup/city/214.78.120.1,214.78.120.2,81.2.69.142")
+        .to_request();
+    let resp: serde_json::Value = actix_test::call_and_read_body_json(&service, req).await;
+
+    // Merged with the database record
+    let merged = &resp["results"]["214.78.120.1"];
+    assert_eq!(merged["country"]["iso_code"], "DE");
+    assert_eq!(merged["city"]["names"]["en"], "San Diego");
+    // Replaced by the more specific network
+    assert_eq!(
+        resp["results"]["214.78.120.2"],
+        json!({"country": {"iso_code": "FR"}, "postal": {"code": "92101"}})
+    );
+    assert_eq!(resp["results"]["81.2.69.142"]["country"]["iso_code"], "GB");
+    assert_eq!(
+        resp["overrides"],
+        json!({"214.78.120.1": "214.78.120.0/24", "214.78.120.2": "214.78.120.2/32"})
+    );
+
+    let req = actix_test::TestRequest::get()
+        .uri("/geoip/lookup/city/214.78.120.1?format=flat")
+        .to_request();
+    let resp: serde_json::Value = actix_test::call_and_read_body_json(&service, req).await;
+    assert_eq!(resp["results"]["214.78.120.1"]["country_code"], "DE");
+    assert_eq!(resp["results"]["214.78.120.1"]["asn"], 64512);
+    assert_eq!(resp["results"]["214.78.120.1"]["city"], "San Diego");
+
+    // Not marked when nothing was overridden
+    let req = actix_test::TestRequest::get()
+        .uri("/geoip/lookup/city/81.2.69.142")
+        .to_request();
+    let resp: serde_json::Value = actix_test::call_and_read_body_json(&service, req).await;
+    assert!(resp.get("overrides").is_none());
+
+    let req = actix_test::TestRequest::get()
+        .uri("/geoip/lookup/city/214.78.120.1,81.2.69.142?format=ndjson")
+        .to_request();
+    let body = actix_test::call_and_read_body(&service, req).await;
+    let lines: Vec<serde_json::Value> = body
+        .split(|byte| *byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice(line).unwrap())
+        .collect();
+    for line in lines {
+        match line["ip"].as_str().unwrap() {
+            "214.78.120.1" => assert_eq!(line["overlay"], "214.78.120.0/24"),
+            _ => assert!(line.get("overlay").is_none()),
+        }
+    }
+}
+
+#[actix_web::test]
+async fn test_flat_lookup_with_overlay_keeps_other_results() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("overlay.csv");
+    std::fs::write(&path, CSV_OVERLAY).unwrap();
+
+    let db = init_db(Path::new("tests-data/"), DbProvider::Local)
+        .await
+        .with_overlay(Some(Arc::new(Overlay::load(&path).unwrap())));
+    let service = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(db))
+            .service(atlas_rs::services::lookup::handle),
+    )
+    .await;
+
+    let req = actix_test::TestRequest::get()
+        .uri("/geoip/lookup/city/214.78.120.1,214.78.120.2,2.125.160.216?format=flat")
+        .to_request();
+    let resp: serde_json::Value = actix_test::call_and_read_body_json(&service, req).await;
+
+    // Flattened like without an overlay
+    let untouched = &resp["results"]["2.125.160.216"];
+    assert_eq!(untouched["country_code"], "GB");
+    assert_eq!(untouched["region_code"], "ENG");
+    assert_eq!(untouched["city"], "Boxford");
+
+    // Only the overridden fields change
+    let merged = &resp["results"]["214.78.120.1"];
+    assert_eq!(merged["country_code"], "DE");
+    assert_eq!(merged["country_name"], "Germany");
+    assert_eq!(merged["asn"], 64512);
+    assert_eq!(merged["city"], "San Diego");
+    assert_eq!(merged["region_code"], "CA");
+
+    let replaced = &resp["results"]["214.78.120.2"];
+    assert_eq!(replaced["country_code"], "FR");
+    assert_eq!(replaced["postal"], "92101");
+    assert!(replaced["city"].is_null());
+}
+
+#[test]
+fn test_reload_if_changed() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("overlay.yml");
+    std::fs::write(&path, YAML_OVERLAY).unwrap();
+
+    let overlay = Overlay::load(&path).unwrap();
+    assert_eq!(overlay.len(), 2);
+    assert!(!overlay.reload_if_changed().unwrap());
+
+    let modified = SystemTime::now() + Duration::from_secs(10);
+    std::fs::write(&path, "- network: 214.78.0.0/16\n  mode: replace\n").unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+
+    assert!(overlay.reload_if_changed().unwrap());
+    assert_eq!(overlay.len(), 1);
+    let entry = overlay.find("214.78.120.1".parse().unwrap()).unwrap();
+    assert_eq!(entry.network.to_string(), "214.78.0.0/16");
+    assert!(overlay.find("4.2.2.4".parse().unwrap()).is_none());
+
+    // The current entries are kept when the file is invalid
+    std::fs::write(&path, "- network: invalid\n").unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(modified + Duration::from_secs(10))
+        .unwrap();
+
+    assert!(overlay.reload_if_changed().is_err());
+    assert_eq!(overlay.len(), 1);
+}
@@ -0,0 +1,285 @@
+use actix_web::http::Method;
+use actix_web::http::header::{CONTENT_DISPOSITION, ETAG as ETAG_HEADER, IF_NONE_MATCH};
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, web};
+use atlas_rs::db_refresher::UpdatableDB;
+use atlas_rs::download_utils::AlreadyDownloaded;
+use atlas_rs::maxmind_db::{DbLayout, MaxmindDB};
+use atlas_rs::providers::{DbProvider, Downloader};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::io::Write;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const TEST_DB: &str = "tests-data/GeoIP2-City-Test_1/GeoIP2-City-Test.mmdb";
+
+const ETAG: &str = "\"v1\"";
+const MAXMIND_FILENAME: &str = "GeoIP2-City-Test_20240101.tar.gz";
+
+/// Serves the test database as `/{variant}.mmdb` and `/{variant}.mmdb.gz` with an ETag, and
+/// MaxMind style download headers on `/maxmind/{variant}`. Returns the base URL and a counter of
+/// the started downloads.
+fn start_db_server() -> (String, Arc<AtomicUsize>) {
+    let db = std::fs::read(TEST_DB).unwrap();
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&db).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let downloads = Arc::new(AtomicUsize::new(0));
+    let server_downloads = downloads.clone();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = HttpServer::new(move || {
+        let serve = |body: Vec<u8>, downloads: Arc<AtomicUsize>| {
+            move |req: HttpRequest| {
+                let body = body.clone();
+                let downloads = downloads.clone();
+
+                async move {
+                    if req
+                        .headers()
+                        .get(IF_NONE_MATCH)
+                        .is_some_and(|etag| etag == ETAG)
+                    {
+                        return HttpResponse::NotModified().finish();
+                    }
+
+                    downloads.fetch_add(1, Ordering::SeqCst);
+                    HttpResponse::Ok()
+                        .insert_header((ETAG_HEADER, ETAG))
+                        .body(body)
+                }
+            }
+        };
+
+        let maxmind_downloads = server_downloads.clone();
+
+        App::new()
+            .route(
+                "/{variant}.mmdb",
+                web::get().to(serve(db.clone(), server_downloads.clone())),
+            )
+            .route(
+                "/{variant}.mmdb.gz",
+                web::get().to(serve(compressed.clone(), server_downloads.clone())),
+            )
+            .route(
+                "/maxmind/{variant}",
+                web::route().to(move |req: HttpRequest| {
+                    let downloads = maxmind_downloads.clone();
+
+                    async move {
+                        if req.method() != Method::HEAD {
+                            downloads.fetch_add(1, Ordering::SeqCst);
+                        }
+
+                        HttpResponse::Ok()
+                            .insert_header((
+                                CONTENT_DISPOSITION,
+                                format!("attachment; filename={MAXMIND_FILENAME}"),
+                            ))
+                            .finish()
+                    }
+                }),
+            )
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+
+    tokio::spawn(server);
+
+    (format!("http://127.0.0.1:{port}"), downloads)
+}
+
+async fn assert_fetches_versioned_db(provider: DbProvider) {
+    let dir = tempfile::tempdir().unwrap();
+    let output_path = dir.path().to_str().unwrap();
+
+    let db_dir = provider
+        .fetch_latest_db(
+            &Downloader::from_env(&provider).unwrap(),
+            "city",
+            output_path,
+        )
+        .await
+        .unwrap();
+
+    assert!(
+        db_dir
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("city_20")
+    );
+    assert_eq!(
+        std::fs::read(db_dir.join("city.mmdb")).unwrap(),
+        std::fs::read(TEST_DB).unwrap()
+    );
+
+    let error = provider
+        .fetch_latest_db(
+            &Downloader::from_env(&provider).unwrap(),
+            "city",
+            output_path,
+        )
+        .await
+        .unwrap_err();
+    assert!(error.downcast_ref::<AlreadyDownloaded>().is_some());
+}
+
+#[actix_web::test]
+async fn test_url_provider_fetches_mmdb() {
+    let (base_url, _) = start_db_server();
+
+    assert_fetches_versioned_db(DbProvider::Url {
+        url: format!("{base_url}/{{VARIANT}}.mmdb"),
+        token: None,
+    })
+    .await;
+}
+
+#[actix_web::test]
+async fn test_url_provider_fetches_gzipped_mmdb() {
+    let (base_url, _) = start_db_server();
+
+    assert_fetches_versioned_db(DbProvider::Url {
+        url: format!("{base_url}/{{VARIANT}}.mmdb.gz?token={{TOKEN}}"),
+        token: Some("secret".to_string()),
+    })
+    .await;
+}
+
+#[actix_web::test]
+async fn test_init_with_provider_downloads_missing_db() {
+    let (base_url, _) = start_db_server();
+    let dir = tempfile::tempdir().unwrap();
+
+    let provider = DbProvider::Url {
+        url: format!("{base_url}/{{VARIANT}}.mmdb"),
+        token: None,
+    };
+    let db = MaxmindDB::init_with_provider(
+        "dbip-city-lite",
+        dir.path().to_str().unwrap(),
+        provider,
+        DbLayout::Versioned,
+    )
+    .await
+    .unwrap();
+
+    let db = db.db.read().await;
+    assert!(
+        db.lookup_by_type("raw", vec!["214.78.120.1".parse().unwrap()])
+            .await
+            .is_some()
+    );
+}
+
+#[actix_web::test]
+async fn test_flat_layout() {
+    let (base_url, _) = start_db_server();
+    let dir = tempfile::tempdir().unwrap();
+
+    let provider = DbProvider::Url {
+        url: format!("{base_url}/{{VARIANT}}.mmdb"),
+        token: None,
+    };
+    let db = MaxmindDB::init_with_provider(
+        "city",
+        dir.path().to_str().unwrap(),
+        provider,
+        DbLayout::Flat,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        db.db.read().await.base_path,
+        dir.path().to_str().unwrap().to_string()
+    );
+
+    // The same database is downloaded again, but not replaced
+    db.update_db(0).await.unwrap();
+
+    let files: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert!(
+        files
+            .iter()
+            .all(|file| file.to_str().unwrap().starts_with("city."))
+    );
+    assert!(files.contains(&"city.mmdb".into()));
+}
+
+#[actix_web::test]
+async fn test_url_provider_skips_unmodified_db() {
+    let (base_url, downloads) = start_db_server();
+    let dir = tempfile::tempdir().unwrap();
+    let output_path = dir.path().to_str().unwrap();
+
+    let provider = DbProvider::Url {
+        url: format!("{base_url}/{{VARIANT}}.mmdb"),
+        token: None,
+    };
+
+    let db_dir = provider
+        .fetch_latest_db(
+            &Downloader::from_env(&provider).unwrap(),
+            "city",
+            output_path,
+        )
+        .await
+        .unwrap();
+    std::fs::remove_dir_all(db_dir).unwrap();
+
+    // The ETag of the first download is sent and nothing is downloaded again
+    let error = provider
+        .fetch_latest_db(
+            &Downloader::from_env(&provider).unwrap(),
+            "city",
+            output_path,
+        )
+        .await
+        .unwrap_err();
+
+    assert!(error.downcast_ref::<AlreadyDownloaded>().is_some());
+    assert_eq!(downloads.load(Ordering::SeqCst), 1);
+}
+
+#[actix_web::test]
+async fn test_maxmind_provider_checks_existing_db_before_download() {
+    let (base_url, downloads) = start_db_server();
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(
+        dir.path()
+            .join(MAXMIND_FILENAME.trim_end_matches(".tar.gz")),
+    )
+    .unwrap();
+
+    let provider = DbProvider::MaxMind {
+        download_url: format!("{base_url}/maxmind/{{VARIANT}}"),
+        account_id: Some("1".to_string()),
+        license_key: Some("key".to_string()),
+        proxy: None,
+    };
+
+    let error = provider
+        .fetch_latest_db(
+            &Downloader::from_env(&provider).unwrap(),
+            "GeoIP2-City-Test",
+            dir.path().to_str().unwrap(),
+        )
+        .await
+        .unwrap_err();
+
+    assert!(error.downcast_ref::<AlreadyDownloaded>().is_some());
+    assert_eq!(downloads.load(Ordering::SeqCst), 0);
+}
@@ -0,0 +1,287 @@
+use actix_http::body::MessageBody;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::{App, http::StatusCode, middleware, test, web::Data};
+use atlas_rs::auth::{self, ApiKeyStore};
+use atlas_rs::network_utils::ClientIpConfig;
+use atlas_rs::rate_limit::{self, RateLimiter};
+
+mod common;
+
+use common::load_test_db;
+
+async fn setup(
+    rate_limiter: RateLimiter,
+    api_keys: Option<&str>,
+) -> impl Service<
+    actix_http::Request,
+    Error = actix_web::Error,
+    Response = ServiceResponse<impl MessageBody>,
+> {
+    let app_data = load_test_db().await;
+
+    let mut app = App::new()
+        .app_data(app_data)
+        .app_data(Data::new(rate_limiter));
+
+    if let Some(keys) = api_keys {
+        let api_keys = ApiKeyStore::load(Some(keys), None).await.unwrap().unwrap();
+        app = app.app_data(Data::new(api_keys));
+    }
+
+    test::init_service(
+        app.wrap(middleware::from_fn(rate_limit::limit_lookups))
+            .wrap(middleware::from_fn(auth::require_api_key))
+            .service(atlas_rs::services::lookup::handle),
+    )
+    .await
+}
+
+fn lookup_request(ip_addresses: &str, peer: &str, api_key: Option<&str>) -> actix_http::Request {
+    let mut req = test::TestRequest::get()
+        .uri(&format!("/geoip/lookup/city/{ip_addresses}"))
+        .peer_addr(peer.parse().unwrap());
+
+    if let Some(api_key) = api_key {
+        req = req.insert_header(("X-API-Key", api_key));
+    }
+
+    req.to_request()
+}
+
+#[actix_web::test]
+async fn test_limits_single_lookups_per_client_ip() {
+    let service = setup(RateLimiter::new(Some(2), None).unwrap(), None).await;
+
+    for _ in 0..2 {
+        let req = lookup_request("214.78.120.1", "81.2.69.142:1234", None);
+        assert!(
+            test::call_service(&service, req)
+                .await
+                .status()
+                .is_success()
+        );
+    }
+
+    let req = lookup_request("214.78.120.1", "81.2.69.142:1234", None);
+    let resp = test::call_service(&service, req).await;
+
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(resp.headers().contains_key("retry-after"));
+
+    let resp: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(resp["error"]["code"], "RATE_LIMITED");
+
+    // Other clients have their own limits
+    let req = lookup_request("214.78.120.1", "81.2.69.143:1234", None);
+    assert!(
+        test::call_service(&service, req)
+            .await
+            .status()
+            .is_success()
+    );
+}
+
+#[actix_web::test]
+async fn test_batch_lookups_count_ip_addresses() {
+    let service = setup(RateLimiter::new(Some(1), Some(3)).unwrap(), None).await;
+
+    let req = lookup_request("214.78.120.1,214.78.120.2", "81.2.69.142:1234", None);
+    assert!(
+        test::call_service(&service, req)
+            .await
+            .status()
+            .is_success()
+    );
+
+    let req = lookup_request("214.78.120.1,214.78.120.2", "81.2.69.142:1234", None);
+    let resp = test::call_service(&service, req).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // Single lookups have a separate limit
+    let req = lookup_request("214.78.120.1", "81.2.69.142:1234", None);
+    assert!(
+        test::call_service(&service, req)
+            .await
+            .status()
+            .is_success()
+    );
+}
+
+#[actix_web::test]
+async fn test_rejects_batches_larger_than_limit() {
+    let service = setup(RateLimiter::new(Some(1), Some(3)).unwrap(), None).await;
+
+    // Refused even with a full bucket, as waiting would not help
+    let req = lookup_request(
+        "214.78.120.1,214.78.120.2,214.78.120.3,214.78.120.4",
+        "81.2.69.142:1234",
+        None,
+    );
+    let resp = test::call_service(&service, req).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(resp.headers().get("Retry-After").is_none());
+    let body = test::read_body(resp).await;
+    assert!(String::from_utf8_lossy(&body).contains("RATE_LIMITED"));
+
+    // Nothing was taken
+    let req = lookup_request(
+        "214.78.120.1,214.78.120.2,214.78.120.3",
+        "81.2.69.142:1234",
+        None,
+    );
+    assert!(
+        test::call_service(&service, req)
+            .await
+            .status()
+            .is_success()
+    );
+}
+
+#[actix_web::test]
+async fn test_counts_percent_encoded_batch_ip_addresses() {
+    let service = setup(RateLimiter::new(Some(10), Some(2)).unwrap(), None).await;
+
+    let req = lookup_request("214.78.120.1,214.78.120.2", "81.2.69.142:1234", None);
+    assert!(
+        test::call_service(&service, req)
+            .await
+            .status()
+            .is_success()
+    );
+
+    // Counts as a batch, not as a single lookup
+    let req = lookup_request(
+        "214.78.120.1%2C214.78.120.2%2C214.78.120.3",
+        "81.2.69.142:1234",
+        None,
+    );
+    let resp = test::call_service(&service, req).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_web::test]
+async fn test_zero_limit_refuses_lookups() {
+    let service = setup(RateLimiter::new(Some(0), None).unwrap(), None).await;
+
+    let req = lookup_request("214.78.120.1", "81.2.69.142:1234", None);
+    let resp = test::call_service(&service, req).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(resp.headers().get("Retry-After").is_none());
+}
+
+#[actix_web::test]
+async fn test_limits_ipv6_clients_by_network() {
+    let service = setup(RateLimiter::new(Some(1), None).unwrap(), None).await;
+
+    let req = lookup_request("214.78.120.1", "[2001:db8::1]:1234", None);
+    assert!(
+        test::call_service(&service, req)
+            .await
+            .status()
+            .is_success()
+    );
+
+    let req = lookup_request("214.78.120.1", "[2001:db8::2]:1234", None);
+    let resp = test::call_service(&service, req).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    let req = lookup_request("214.78.120.1", "[2001:db8:0:1::1]:1234", None);
+    assert!(
+        test::call_service(&service, req)
+            .await
+            .status()
+            .is_success()
+    );
+}
+
+#[actix_web::test]
+async fn test_bounds_tracked_clients() {
+    let rate_limiter = Data::new(
+        RateLimiter::new(Some(5), None)
+            .unwrap()
+            .with_max_tracked_clients(4),
+    );
+    let service = test::init_service(
+        App::new()
+            .app_data(load_test_db().await)
+            .app_data(rate_limiter.clone())
+            .wrap(middleware::from_fn(rate_limit::limit_lookups))
+            .service(atlas_rs::services::lookup::handle),
+    )
+    .await;
+
+    // All clients stay active, the least recently seen ones are evicted
+    for client in 0..20 {
+        let req = lookup_request("214.78.120.1", &format!("81.2.69.{client}:1234"), None);
+        assert!(
+            test::call_service(&service, req)
+                .await
+                .status()
+                .is_success()
+        );
+        assert!(rate_limiter.tracked_clients() <= 4);
+    }
+
+    // The most recent client is still limited
+    for _ in 0..4 {
+        let req = lookup_request("214.78.120.1", "81.2.69.19:1234", None);
+        test::call_service(&service, req).await;
+    }
+    let req = lookup_request("214.78.120.1", "81.2.69.19:1234", None);
+    let resp = test::call_service(&service, req).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_web::test]
+async fn test_limits_requests_with_unparsable_forwarded_hop() {
+    let service = test::init_service(
+        App::new()
+            .app_data(load_test_db().await)
+            .app_data(Data::new(RateLimiter::new(Some(1), None).unwrap()))
+            .app_data(Data::new(
+                ClientIpConfig::parse("10.0.0.0/8", "Forwarded").unwrap(),
+            ))
+            .wrap(middleware::from_fn(rate_limit::limit_lookups))
+            .service(atlas_rs::services::lookup::handle),
+    )
+    .await;
+
+    let forwarded_request = || {
+        test::TestRequest::get()
+            .uri("/geoip/lookup/city/214.78.120.1")
+            .peer_addr("10.0.0.1:1234".parse().unwrap())
+            .insert_header(("Forwarded", "for=unknown"))
+            .to_request()
+    };
+
+    let resp = test::call_service(&service, forwarded_request()).await;
+    assert!(resp.status().is_success());
+
+    let resp = test::call_service(&service, forwarded_request()).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_web::test]
+async fn test_limits_by_api_key() {
+    let service = setup(RateLimiter::new(Some(1), None).unwrap(), Some("one,two")).await;
+
+    let req = lookup_request("214.78.120.1", "81.2.69.142:1234", Some("one"));
+    assert!(
+        test::call_service(&service, req)
+            .await
+            .status()
+            .is_success()
+    );
+
+    let req = lookup_request("214.78.120.1", "81.2.69.142:1234", Some("two"));
+    assert!(
+        test::call_service(&service, req)
+            .await
+            .status()
+            .is_success()
+    );
+
+    let req = lookup_request("214.78.120.1", "81.2.69.143:1234", Some("one"));
+    let resp = test::call_service(&service, req).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+}
@@ -0,0 +1,273 @@
+use actix_web::{App, HttpResponse, HttpServer, test as actix_test, web};
+use atlas_rs::ServerConfig;
+use atlas_rs::db_refresher::UpdatableDB;
+use atlas_rs::maxmind_db::{DbLayout, MaxmindDB};
+use atlas_rs::mirror::MirrorConfig;
+use atlas_rs::network_utils::ClientIpConfig;
+use atlas_rs::providers::DbProvider;
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+const TEST_DB: &str = "tests-data/GeoIP2-City-Test_1/GeoIP2-City-Test.mmdb";
+const VARIANT: &str = "GeoIP2-City-Test";
+
+/// Serves the test database on `/{variant}.mmdb` once `available` is set, answering with
+/// `404 Not Found` until then
+fn start_db_server(available: Arc<AtomicBool>) -> String {
+    let db = std::fs::read(TEST_DB).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = HttpServer::new(move || {
+        let db = db.clone();
+        let available = available.clone();
+
+        App::new().route(
+            "/{variant}.mmdb",
+            web::get().to(move || {
+                let db = db.clone();
+                let available = available.load(Ordering::SeqCst);
+                async move {
+                    match available {
+                        true => HttpResponse::Ok().body(db),
+                        false => HttpResponse::NotFound().finish(),
+                    }
+                }
+            }),
+        )
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+
+    tokio::spawn(server);
+
+    format!("http://127.0.0.1:{port}/{{VARIANT}}.mmdb")
+}
+
+#[actix_web::test]
+async fn test_replaces_fallback_database() {
+    let available = Arc::new(AtomicBool::new(false));
+    let provider = DbProvider::Url {
+        url: start_db_server(available.clone()),
+        token: None,
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let base_path = dir.path().to_str().unwrap();
+
+    let db = MaxmindDB::init_with_fallback(
+        VARIANT,
+        base_path,
+        provider,
+        DbLayout::Versioned,
+        Path::new(TEST_DB),
+    )
+    .await
+    .unwrap();
+
+    {
+        let inner = db.db.read().await;
+        assert!(inner.is_fallback());
+        assert!(
+            inner
+                .lookup_by_type("city", vec!["81.2.69.142".parse().unwrap()])
+                .await
+                .is_some()
+        );
+    }
+
+    // Still unavailable, the fallback is kept
+    assert!(db.update_db(0).await.is_err());
+    assert!(db.db.read().await.is_fallback());
+
+    // Replaced regardless of the minimum age once the upstream is available
+    available.store(true, Ordering::SeqCst);
+    db.update_db(u64::MAX / 2).await.unwrap();
+
+    let inner = db.db.read().await;
+    assert!(!inner.is_fallback());
+    assert!(
+        inner
+            .base_path
+            .starts_with(&format!("{base_path}/{VARIANT}_"))
+    );
+    assert!(Path::new(TEST_DB).exists());
+}
+
+#[actix_web::test]
+async fn test_mirror_refuses_fallback_database() {
+    let provider = DbProvider::Url {
+        url: start_db_server(Arc::new(AtomicBool::new(false))),
+        token: None,
+    };
+    let dir = tempfile::tempdir().unwrap();
+
+    let db = MaxmindDB::init_with_fallback(
+        VARIANT,
+        dir.path().to_str().unwrap(),
+        provider,
+        DbLayout::Versioned,
+        Path::new(TEST_DB),
+    )
+    .await
+    .unwrap();
+
+    let service = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(db))
+            .app_data(web::Data::new(MirrorConfig::new("secret".to_string())))
+            .service(atlas_rs::services::mirror::handle),
+    )
+    .await;
+
+    let req = actix_test::TestRequest::get()
+        .uri(&format!("/mirror/{VARIANT}"))
+        .insert_header(("Authorization", "Bearer secret"))
+        .to_request();
+    let resp = actix_test::call_service(&service, req).await;
+
+    assert_eq!(resp.status(), 503);
+}
+
+#[actix_web::test]
+async fn test_keeps_unloadable_database_in_use() {
+    let provider = DbProvider::Url {
+        url: start_db_server(Arc::new(AtomicBool::new(true))),
+        token: None,
+    };
+    let dir = tempfile::tempdir().unwrap();
+
+    let db = MaxmindDB::init_with_provider(
+        VARIANT,
+        dir.path().to_str().unwrap(),
+        provider,
+        DbLayout::Versioned,
+    )
+    .await
+    .unwrap();
+
+    // Corrupted on disk after it was loaded
+    let db_dir = db.db.read().await.base_path.clone();
+    let db_file = Path::new(&db_dir).join(format!("{VARIANT}.mmdb"));
+    std::fs::write(&db_file, "not a database").unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&db_file)
+        .unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(10))
+        .unwrap();
+
+    // Still in use by the loaded database, so it is neither removed nor moved
+    let error = db.update_db(u64::MAX / 2).await.unwrap_err();
+    assert!(error.to_string().contains("is in use"));
+
+    assert!(db_file.is_file());
+    let db_dir_name = Path::new(&db_dir).file_name().unwrap().to_str().unwrap();
+    assert!(
+        !dir.path()
+            .join(format!(".{db_dir_name}.unloadable.tmp"))
+            .exists()
+    );
+}
+
+#[actix_web::test]
+async fn test_removes_corrupt_database_which_is_not_in_use() {
+    let available = Arc::new(AtomicBool::new(false));
+    let provider = DbProvider::Url {
+        url: start_db_server(available.clone()),
+        token: None,
+    };
+    let dir = tempfile::tempdir().unwrap();
+
+    let db = MaxmindDB::init_with_fallback(
+        VARIANT,
+        dir.path().to_str().unwrap(),
+        provider,
+        DbLayout::Versioned,
+        Path::new(TEST_DB),
+    )
+    .await
+    .unwrap();
+
+    // Placed by another process, but not loaded by any
+    let db_dir = dir.path().join(format!("{VARIANT}_20000101"));
+    std::fs::create_dir(&db_dir).unwrap();
+    std::fs::write(db_dir.join(format!("{VARIANT}.mmdb")), "not a database").unwrap();
+
+    available.store(true, Ordering::SeqCst);
+    db.update_db(0).await.unwrap();
+
+    assert!(!db.db.read().await.is_fallback());
+    assert!(!db_dir.exists());
+    assert!(
+        !dir.path()
+            .join(format!(".{VARIANT}_20000101.unloadable.tmp"))
+            .exists()
+    );
+}
+
+#[actix_web::test]
+async fn test_not_ready_server() {
+    let port = {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().port()
+    };
+
+    let config = ServerConfig {
+        host: "127.0.0.1".to_string(),
+        port,
+        tcp_enabled: true,
+        unix_socket: None,
+        swagger_ui_enabled: false,
+        client_ip_config: ClientIpConfig::default(),
+        api_keys: None,
+        rate_limiter: None,
+        tls: None,
+        mirror: None,
+        admin: None,
+    };
+
+    let server = atlas_rs::start_not_ready_server(&config);
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+
+    let resp = reqwest::get(format!("http://127.0.0.1:{port}/health"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 503);
+    assert!(resp.text().await.unwrap().contains("NOT_READY"));
+
+    handle.stop(true).await;
+    assert!(
+        reqwest::get(format!("http://127.0.0.1:{port}/health"))
+            .await
+            .is_err()
+    );
+}
+
+#[actix_web::test]
+async fn test_waits_for_database() {
+    let dir = tempfile::tempdir().unwrap();
+    let base_path = dir.path().to_str().unwrap().to_string();
+
+    let db_dir = dir.path().join(format!("{VARIANT}_20240101"));
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        std::fs::create_dir(&db_dir).unwrap();
+        std::fs::copy(TEST_DB, db_dir.join(format!("{VARIANT}.mmdb"))).unwrap();
+    });
+
+    let db = tokio::time::timeout(
+        Duration::from_secs(10),
+        atlas_rs::wait_for_db(&base_path, VARIANT, Duration::from_millis(100)),
+    )
+    .await
+    .unwrap();
+
+    assert!(db.db.read().await.base_path.ends_with("_20240101"));
+}
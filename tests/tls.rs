@@ -0,0 +1,116 @@
+use actix_web::{App, HttpServer};
+use atlas_rs::tls::{ReloadingCertResolver, TlsConfig};
+use rustls::pki_types::CertificateDer;
+use rustls::pki_types::pem::PemObject;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+const TLS_DIR: &str = "tests-data/tls";
+
+fn tls_config(client_ca: bool) -> TlsConfig {
+    TlsConfig {
+        cert_path: Path::new(TLS_DIR).join("server.pem"),
+        key_path: Path::new(TLS_DIR).join("server-key.pem"),
+        client_ca_path: client_ca.then(|| Path::new(TLS_DIR).join("ca.pem")),
+        reload_interval: Duration::from_secs(60),
+    }
+}
+
+fn read_cert(path: &Path) -> CertificateDer<'static> {
+    CertificateDer::from_pem_file(path).unwrap()
+}
+
+/// Starts a TLS server with the healthcheck endpoint and returns its address
+fn start_server(tls: &TlsConfig) -> String {
+    let resolver = Arc::new(ReloadingCertResolver::load(&tls.cert_path, &tls.key_path).unwrap());
+    let server_config = tls.server_config(resolver).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = HttpServer::new(|| App::new().service(atlas_rs::services::healthcheck::handle))
+        .workers(1)
+        .listen_rustls_0_23(listener, server_config)
+        .unwrap()
+        .run();
+
+    tokio::spawn(server);
+
+    format!("https://localhost:{port}/health")
+}
+
+fn client_builder() -> reqwest::ClientBuilder {
+    let ca = std::fs::read(Path::new(TLS_DIR).join("ca.pem")).unwrap();
+
+    reqwest::Client::builder()
+        .tls_certs_only([reqwest::Certificate::from_pem(&ca).unwrap()])
+        .resolve("localhost", "127.0.0.1:0".parse().unwrap())
+}
+
+#[actix_web::test]
+async fn test_serves_https() {
+    let url = start_server(&tls_config(false));
+    let client = client_builder().build().unwrap();
+
+    let resp = client.get(url).send().await.unwrap();
+
+    assert!(resp.status().is_success());
+    assert_eq!(resp.text().await.unwrap(), "Ok");
+}
+
+#[actix_web::test]
+async fn test_mutual_tls_requires_client_certificate() {
+    let url = start_server(&tls_config(true));
+
+    let client = client_builder().build().unwrap();
+    assert!(client.get(&url).send().await.is_err());
+
+    let mut identity = std::fs::read(Path::new(TLS_DIR).join("client.pem")).unwrap();
+    identity.extend(std::fs::read(Path::new(TLS_DIR).join("client-key.pem")).unwrap());
+
+    let client = client_builder()
+        .identity(reqwest::Identity::from_pem(&identity).unwrap())
+        .build()
+        .unwrap();
+
+    assert!(client.get(&url).send().await.unwrap().status().is_success());
+}
+
+#[test]
+fn test_reloads_changed_certificate() {
+    let dir = tempfile::tempdir().unwrap();
+    let cert_path: PathBuf = dir.path().join("cert.pem");
+    let key_path: PathBuf = dir.path().join("key.pem");
+
+    std::fs::copy(Path::new(TLS_DIR).join("server.pem"), &cert_path).unwrap();
+    std::fs::copy(Path::new(TLS_DIR).join("server-key.pem"), &key_path).unwrap();
+
+    let resolver = ReloadingCertResolver::load(&cert_path, &key_path).unwrap();
+    assert!(!resolver.reload_if_changed().unwrap());
+    assert_eq!(
+        resolver.certificate_chain()[0],
+        read_cert(&Path::new(TLS_DIR).join("server.pem"))
+    );
+
+    std::fs::copy(Path::new(TLS_DIR).join("server2.pem"), &cert_path).unwrap();
+    std::fs::copy(Path::new(TLS_DIR).join("server2-key.pem"), &key_path).unwrap();
+
+    // Make sure the modification time changes even on filesystems with coarse timestamps
+    let modified = SystemTime::now() + Duration::from_secs(10);
+    for path in [&cert_path, &key_path] {
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    assert!(resolver.reload_if_changed().unwrap());
+    assert_eq!(
+        resolver.certificate_chain()[0],
+        read_cert(&Path::new(TLS_DIR).join("server2.pem"))
+    );
+}
@@ -0,0 +1,96 @@
+#![cfg(unix)]
+
+use atlas_rs::network_utils::ClientIpConfig;
+use atlas_rs::{ServerConfig, UnixSocketConfig};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+mod common;
+
+use common::load_test_db;
+
+async fn start_server(socket_path: PathBuf, mode: Option<u32>) {
+    let app_data = load_test_db().await;
+
+    let config = ServerConfig {
+        host: "127.0.0.1".to_string(),
+        port: 0,
+        tcp_enabled: false,
+        unix_socket: Some(UnixSocketConfig {
+            path: socket_path.clone(),
+            mode,
+        }),
+        swagger_ui_enabled: false,
+        client_ip_config: ClientIpConfig::default(),
+        api_keys: None,
+        rate_limiter: None,
+        tls: None,
+        mirror: None,
+        admin: None,
+    };
+
+    actix_web::rt::spawn(atlas_rs::start_server(app_data, config));
+
+    for _ in 0..100 {
+        if UnixStream::connect(&socket_path).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    panic!(
+        "Server did not start listening on {}",
+        socket_path.display()
+    );
+}
+
+async fn get(socket_path: &Path, path: &str) -> String {
+    let mut stream = UnixStream::connect(socket_path).await.unwrap();
+    let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[actix_web::test]
+async fn test_serves_on_unix_socket() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("atlas.sock");
+    start_server(socket_path.clone(), Some(0o660)).await;
+
+    let response = get(&socket_path, "/health").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+    let response = get(&socket_path, "/geoip/lookup/city/81.2.69.142").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains("\"database_build_epoch\""));
+
+    let mode = std::fs::metadata(&socket_path)
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o660);
+
+    // Bound in a private directory first, which is removed again
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[actix_web::test]
+async fn test_replaces_stale_socket() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("atlas.sock");
+
+    // Simulate a socket left behind by a crashed process
+    drop(std::os::unix::net::UnixListener::bind(&socket_path).unwrap());
+    assert!(socket_path.exists());
+
+    start_server(socket_path.clone(), None).await;
+
+    let response = get(&socket_path, "/health").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+}
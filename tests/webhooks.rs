@@ -0,0 +1,212 @@
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, web};
+use atlas_rs::db_refresher::UpdatableDB;
+use atlas_rs::download_utils::RetryPolicy;
+use atlas_rs::maxmind_db::MaxmindDB;
+use atlas_rs::providers::DbProvider;
+use atlas_rs::webhooks::{self, EVENT_HEADER, SIGNATURE_HEADER, Webhooks};
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+mod common;
+
+use common::{VARIANT, copy_test_db};
+
+const SECRET: &str = "webhook-secret";
+
+#[derive(Debug, Clone)]
+struct Delivery {
+    event: String,
+    signature: Option<String>,
+    body: web::Bytes,
+}
+
+type Deliveries = Arc<Mutex<Vec<Delivery>>>;
+
+/// Starts a receiver failing the first `failures` requests and returns its URL and the
+/// successfully received events
+fn start_receiver(failures: usize) -> (String, Deliveries) {
+    let deliveries = Deliveries::default();
+    let attempts = Arc::new(Mutex::new(0));
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let received = deliveries.clone();
+    let server = HttpServer::new(move || {
+        let received = received.clone();
+        let attempts = attempts.clone();
+
+        App::new().route(
+            "/hook",
+            web::post().to(move |req: HttpRequest, body: web::Bytes| {
+                let received = received.clone();
+                let attempts = attempts.clone();
+
+                async move {
+                    let mut attempts = attempts.lock().unwrap();
+                    *attempts += 1;
+                    if *attempts <= failures {
+                        return HttpResponse::InternalServerError().finish();
+                    }
+
+                    let header = |name| {
+                        req.headers()
+                            .get(name)
+                            .map(|value| value.to_str().unwrap().to_string())
+                    };
+                    received.lock().unwrap().push(Delivery {
+                        event: header(EVENT_HEADER).unwrap(),
+                        signature: header(SIGNATURE_HEADER),
+                        body,
+                    });
+
+                    HttpResponse::NoContent().finish()
+                }
+            }),
+        )
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+
+    tokio::spawn(server);
+
+    (format!("http://127.0.0.1:{port}/hook"), deliveries)
+}
+
+/// Waits until `count` events were received, as they are delivered in the background
+async fn wait_for_deliveries(deliveries: &Deliveries, count: usize) {
+    for _ in 0..100 {
+        if deliveries.lock().unwrap().len() >= count {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+fn webhooks(url: String, secret: Option<&str>) -> Option<Webhooks> {
+    let retry = RetryPolicy {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(50),
+    };
+
+    Some(Webhooks::new(vec![url], secret.map(String::from), retry).unwrap())
+}
+
+async fn init_db(base_path: &Path, webhooks: Option<Webhooks>) -> MaxmindDB {
+    common::init_db(base_path, DbProvider::Local)
+        .await
+        .with_webhooks(webhooks)
+}
+
+#[actix_web::test]
+async fn test_sends_signed_event_when_database_is_swapped() {
+    let (url, deliveries) = start_receiver(1);
+    let dir = tempfile::tempdir().unwrap();
+    copy_test_db(&dir.path().join(format!("{VARIANT}_20240101")));
+
+    let db = init_db(dir.path(), webhooks(url, Some(SECRET))).await;
+
+    // Nothing changed, nothing is sent
+    db.update_db(0).await.unwrap();
+    assert!(deliveries.lock().unwrap().is_empty());
+
+    let new_dir = dir.path().join(format!("{VARIANT}_20240102"));
+    copy_test_db(&new_dir);
+    db.update_db(0).await.unwrap();
+    let build_epoch = db.db.read().await.build_epoch();
+    wait_for_deliveries(&deliveries, 1).await;
+
+    let deliveries = deliveries.lock().unwrap();
+    assert_eq!(deliveries.len(), 1);
+
+    let delivery = &deliveries[0];
+    assert_eq!(delivery.event, "database.updated");
+    assert_eq!(
+        delivery.signature.as_deref(),
+        Some(webhooks::signature(SECRET, &delivery.body).as_str())
+    );
+
+    let event: serde_json::Value = serde_json::from_slice(&delivery.body).unwrap();
+    assert_eq!(event["event"], "database.updated");
+    assert_eq!(event["edition"], VARIANT);
+    assert_eq!(event["success"], true);
+    assert_eq!(event["old_build_epoch"], build_epoch);
+    assert_eq!(event["new_build_epoch"], build_epoch);
+    assert_eq!(event["directory"], new_dir.to_str().unwrap());
+    assert!(event["duration_ms"].is_u64());
+    assert!(event["reason"].is_null());
+}
+
+#[actix_web::test]
+async fn test_sends_event_when_update_fails() {
+    let (url, deliveries) = start_receiver(0);
+    let dir = tempfile::tempdir().unwrap();
+    let db_dir = dir.path().join(format!("{VARIANT}_20240101"));
+    copy_test_db(&db_dir);
+
+    let db = init_db(dir.path(), webhooks(url, None)).await;
+
+    std::fs::remove_dir_all(&db_dir).unwrap();
+    assert!(db.update_db(0).await.is_err());
+    wait_for_deliveries(&deliveries, 1).await;
+
+    let deliveries = deliveries.lock().unwrap();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0].event, "database.update_failed");
+    assert_eq!(deliveries[0].signature, None);
+
+    let event: serde_json::Value = serde_json::from_slice(&deliveries[0].body).unwrap();
+    assert_eq!(event["success"], false);
+    assert_eq!(event["directory"], db_dir.to_str().unwrap());
+    assert_eq!(
+        event["reason"],
+        format!(
+            "No {VARIANT} database found in {}",
+            dir.path().to_str().unwrap()
+        )
+    );
+}
+
+#[actix_web::test]
+async fn test_failed_delivery_does_not_fail_update() {
+    let (url, deliveries) = start_receiver(usize::MAX);
+    let dir = tempfile::tempdir().unwrap();
+    copy_test_db(&dir.path().join(format!("{VARIANT}_20240101")));
+
+    let db = init_db(dir.path(), webhooks(url, Some(SECRET))).await;
+
+    copy_test_db(&dir.path().join(format!("{VARIANT}_20240102")));
+    db.update_db(0).await.unwrap();
+
+    assert!(deliveries.lock().unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn test_slow_receiver_does_not_hold_up_update() {
+    // Connections are accepted by the OS, but never answered
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let dir = tempfile::tempdir().unwrap();
+    copy_test_db(&dir.path().join(format!("{VARIANT}_20240101")));
+
+    let db = init_db(dir.path(), webhooks(url, None)).await;
+
+    copy_test_db(&dir.path().join(format!("{VARIANT}_20240102")));
+    tokio::time::timeout(Duration::from_secs(2), db.update_db(0))
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[test]
+fn test_signature() {
+    assert_eq!(
+        webhooks::signature("key", b"The quick brown fox jumps over the lazy dog"),
+        "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+    );
+}
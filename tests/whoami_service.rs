@@ -0,0 +1,94 @@
+use actix_http::body::MessageBody;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::{App, test, web::Data};
+use atlas_rs::network_utils::ClientIpConfig;
+
+mod common;
+
+use common::load_test_db;
+
+async fn setup(
+    client_ip_config: ClientIpConfig,
+) -> impl Service<
+    actix_http::Request,
+    Error = actix_web::Error,
+    Response = ServiceResponse<impl MessageBody>,
+> {
+    let app_data = load_test_db().await;
+
+    test::init_service(
+        App::new()
+            .app_data(app_data)
+            .app_data(Data::new(client_ip_config))
+            .service(atlas_rs::services::whoami::handle)
+            .service(atlas_rs::services::lookup::handle),
+    )
+    .await
+}
+
+fn trusted_proxy_config(headers: &str) -> ClientIpConfig {
+    ClientIpConfig::parse("10.0.0.0/8, 172.16.0.1", headers).unwrap()
+}
+
+#[actix_web::test]
+async fn test_lookup_peer_address() {
+    let service = setup(ClientIpConfig::default()).await;
+    let req = test::TestRequest::get()
+        .uri("/geoip/lookup/city/me")
+        .peer_addr("214.78.120.1:4321".parse().unwrap())
+        .insert_header(("X-Forwarded-For", "81.2.69.142"))
+        .to_request();
+
+    let resp: serde_json::Value = test::call_and_read_body_json(&service, req).await;
+
+    assert_eq!(
+        resp["results"]["214.78.120.1"]["city"]["names"]["en"],
+        "San Diego"
+    );
+}
+
+#[actix_web::test]
+async fn test_lookup_x_forwarded_for_from_trusted_proxy() {
+    let service = setup(trusted_proxy_config("X-Forwarded-For")).await;
+    let req = test::TestRequest::get()
+        .uri("/geoip/lookup/city/me")
+        .peer_addr("10.1.2.3:4321".parse().unwrap())
+        .insert_header(("X-Forwarded-For", "1.1.1.1, 214.78.120.1, 172.16.0.1"))
+        .to_request();
+
+    let resp: serde_json::Value = test::call_and_read_body_json(&service, req).await;
+
+    assert!(resp["results"].get("214.78.120.1").is_some());
+    assert!(resp["results"].get("1.1.1.1").is_none());
+}
+
+#[actix_web::test]
+async fn test_lookup_forwarded_header() {
+    let service = setup(trusted_proxy_config("CF-Connecting-IP,Forwarded")).await;
+    let req = test::TestRequest::get()
+        .uri("/geoip/lookup/country/me?format=flat")
+        .peer_addr("10.1.2.3:4321".parse().unwrap())
+        .insert_header(("Forwarded", "for=\"214.78.120.1:8080\";proto=https"))
+        .to_request();
+
+    let resp: serde_json::Value = test::call_and_read_body_json(&service, req).await;
+
+    assert_eq!(resp["results"]["214.78.120.1"]["country_code"], "US");
+}
+
+#[actix_web::test]
+async fn test_ignores_headers_from_untrusted_peer() {
+    let service = setup(trusted_proxy_config("X-Forwarded-For")).await;
+    let req = test::TestRequest::get()
+        .uri("/geoip/lookup/city/me")
+        .peer_addr("192.168.1.1:4321".parse().unwrap())
+        .insert_header(("X-Forwarded-For", "214.78.120.1"))
+        .to_request();
+
+    let resp: serde_json::Value = test::call_and_read_body_json(&service, req).await;
+
+    assert_eq!(
+        resp["error"]["code"].as_str().unwrap(),
+        "SPECIAL_IP".to_string()
+    );
+}